@@ -0,0 +1,461 @@
+// Shell arithmetic: 64-bit signed integers with the C operators. Expressions
+// are parsed into a tree first so that `&&`, `||` and `?:` only evaluate the
+// operands they need.
+
+use crate::lexer;
+use crate::shell::Shell;
+
+// How deep variables whose values are themselves expressions may nest.
+const MAX_DEPTH: usize = 1024;
+
+const OPERATORS: [&str; 38] = [
+    "<<=", ">>=", "**", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&", "||", "+=", "-=",
+    "*=", "/=", "%=", "&=", "^=", "|=", "+", "-", "*", "/", "%", "<", ">", "&", "^", "|", "!", "~",
+    "?", ":", "=", ",", "(",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Num(String),
+    Name(String),
+    Op(&'static str),
+    Close,
+}
+
+enum Expr {
+    Num(i64),
+    Var(String),
+    Unary(&'static str, Box<Expr>),
+    // A `++` or `--`, applied before or after reading the variable.
+    Step(String, i64, bool),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    Cond(Box<Expr>, Box<Expr>, Box<Expr>),
+    // `=` or a compound assignment, which carries its binary operator.
+    Assign(String, Option<&'static str>, Box<Expr>),
+}
+
+fn tokenize(src: &str) -> Result<Vec<(Tok, usize)>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let offset = |i: usize| chars[..i].iter().collect::<String>().len();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+
+    'outer: while i < chars.len() {
+        let c = chars[i];
+        let start = offset(i);
+
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            let mut text = String::new();
+            while i < chars.len()
+                && (chars[i].is_ascii_alphanumeric() || matches!(chars[i], '#' | '@' | '_'))
+            {
+                text.push(chars[i]);
+                i += 1;
+            }
+            tokens.push((Tok::Num(text), start));
+        } else if c.is_ascii_alphabetic() || c == '_' || (c == '$' && i + 1 < chars.len()) {
+            // `$name` and `${name}` are accepted for expressions that were
+            // not expanded beforehand, such as substring offsets.
+            let braced = c == '$' && chars[i + 1] == '{';
+            if c == '$' {
+                i += if braced { 2 } else { 1 };
+            }
+            let mut name = String::new();
+            while i < chars.len() && (chars[i].is_ascii_alphanumeric() || chars[i] == '_') {
+                name.push(chars[i]);
+                i += 1;
+            }
+            if braced {
+                if chars.get(i) != Some(&'}') {
+                    return Err(syntax_error("invalid arithmetic operator", &src[start..]));
+                }
+                i += 1;
+            }
+            if !lexer::is_name(&name) {
+                return Err(syntax_error("operand expected", &src[start..]));
+            }
+            tokens.push((Tok::Name(name), start));
+        } else if c == ')' {
+            tokens.push((Tok::Close, start));
+            i += 1;
+        } else {
+            let rest: String = chars[i..].iter().collect();
+            for op in OPERATORS {
+                if rest.starts_with(op) {
+                    tokens.push((Tok::Op(op), start));
+                    i += op.chars().count();
+                    continue 'outer;
+                }
+            }
+            return Err(syntax_error("invalid arithmetic operator", &src[start..]));
+        }
+    }
+
+    Ok(tokens)
+}
+
+fn syntax_error(message: &str, token: &str) -> String {
+    format!(
+        "syntax error: {} (error token is \"{}\")",
+        message,
+        token.trim()
+    )
+}
+
+// Parses an integer constant: decimal, octal with a leading `0`, hex with
+// `0x`, or `base#digits` for bases 2 to 64.
+fn number(text: &str) -> Result<i64, String> {
+    let too_great = || format!("value too great for base (error token is \"{}\")", text);
+
+    let (base, digits) = if let Some((base, digits)) = text.split_once('#') {
+        match base.parse::<u32>() {
+            Ok(base) if (2..=64).contains(&base) => (base, digits),
+            _ => {
+                return Err(format!(
+                    "invalid arithmetic base (error token is \"{}\")",
+                    text
+                ))
+            }
+        }
+    } else if let Some(digits) = text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        (16, digits)
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+
+    if digits.is_empty() {
+        return Err(too_great());
+    }
+
+    let mut value: i64 = 0;
+    for c in digits.chars() {
+        let digit = match c {
+            '0'..='9' => c as u32 - '0' as u32,
+            'a'..='z' => c as u32 - 'a' as u32 + 10,
+            'A'..='Z' if base <= 36 => c as u32 - 'A' as u32 + 10,
+            'A'..='Z' => c as u32 - 'A' as u32 + 36,
+            '@' => 62,
+            '_' => 63,
+            _ => return Err(too_great()),
+        };
+        if digit >= base {
+            return Err(too_great());
+        }
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+    }
+
+    Ok(value)
+}
+
+fn precedence(op: &str) -> Option<u8> {
+    let level = match op {
+        "||" => 1,
+        "&&" => 2,
+        "|" => 3,
+        "^" => 4,
+        "&" => 5,
+        "==" | "!=" => 6,
+        "<" | ">" | "<=" | ">=" => 7,
+        "<<" | ">>" => 8,
+        "+" | "-" => 9,
+        "*" | "/" | "%" => 10,
+        "**" => 11,
+        _ => return None,
+    };
+    Some(level)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    tokens: Vec<(Tok, usize)>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Tok> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.peek() {
+            Some(Tok::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        match self.tokens.get(self.pos) {
+            Some((_, start)) => syntax_error(message, &self.src[*start..]),
+            None => syntax_error("operand expected", ""),
+        }
+    }
+
+    fn expect(&mut self, op: &str) -> Result<(), String> {
+        if self.peek_op() == Some(op) {
+            self.pos += 1;
+            Ok(())
+        } else {
+            Err(self.error(&format!("`{}' expected", op)))
+        }
+    }
+
+    fn comma(&mut self) -> Result<Expr, String> {
+        let mut expr = self.assign()?;
+        while self.peek_op() == Some(",") {
+            self.pos += 1;
+            let right = self.assign()?;
+            expr = Expr::Binary(",", Box::new(expr), Box::new(right));
+        }
+        Ok(expr)
+    }
+
+    fn assign(&mut self) -> Result<Expr, String> {
+        if let Some(Tok::Name(name)) = self.peek() {
+            let op = match self.tokens.get(self.pos + 1) {
+                Some((Tok::Op(op), _))
+                    if op.ends_with('=') && !matches!(*op, "==" | "!=" | "<=" | ">=") =>
+                {
+                    *op
+                }
+                _ => return self.ternary(),
+            };
+            let name = name.clone();
+            self.pos += 2;
+            let value = self.assign()?;
+            let binary = match op {
+                "=" => None,
+                _ => OPERATORS
+                    .iter()
+                    .copied()
+                    .find(|candidate| *candidate == &op[..op.len() - 1]),
+            };
+            return Ok(Expr::Assign(name, binary, Box::new(value)));
+        }
+        self.ternary()
+    }
+
+    fn ternary(&mut self) -> Result<Expr, String> {
+        let cond = self.binary(1)?;
+        if self.peek_op() != Some("?") {
+            return Ok(cond);
+        }
+        self.pos += 1;
+        let then = self.comma()?;
+        self.expect(":")?;
+        let otherwise = self.assign()?;
+        Ok(Expr::Cond(
+            Box::new(cond),
+            Box::new(then),
+            Box::new(otherwise),
+        ))
+    }
+
+    fn binary(&mut self, min: u8) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+
+        while let Some(op) = self.peek_op() {
+            let level = match precedence(op) {
+                Some(level) if level >= min => level,
+                _ => break,
+            };
+            self.pos += 1;
+            // `**` groups to the right, everything else to the left.
+            let next = if op == "**" { level } else { level + 1 };
+            let right = self.binary(next)?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.peek_op() {
+            Some(op @ ("++" | "--")) => {
+                self.pos += 1;
+                match self.peek().cloned() {
+                    Some(Tok::Name(name)) => {
+                        self.pos += 1;
+                        Ok(Expr::Step(name, if op == "++" { 1 } else { -1 }, true))
+                    }
+                    _ => Err(self.error("operand expected")),
+                }
+            }
+            Some(op @ ("-" | "+" | "!" | "~")) => {
+                self.pos += 1;
+                Ok(Expr::Unary(op, Box::new(self.unary()?)))
+            }
+            _ => self.postfix(),
+        }
+    }
+
+    fn postfix(&mut self) -> Result<Expr, String> {
+        let expr = self.primary()?;
+        if let Expr::Var(name) = &expr {
+            if let Some(op @ ("++" | "--")) = self.peek_op() {
+                self.pos += 1;
+                return Ok(Expr::Step(
+                    name.clone(),
+                    if op == "++" { 1 } else { -1 },
+                    false,
+                ));
+            }
+        }
+        Ok(expr)
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.peek().cloned() {
+            Some(Tok::Num(text)) => {
+                self.pos += 1;
+                Ok(Expr::Num(number(&text)?))
+            }
+            Some(Tok::Name(name)) => {
+                self.pos += 1;
+                Ok(Expr::Var(name))
+            }
+            Some(Tok::Op("(")) => {
+                self.pos += 1;
+                let expr = self.comma()?;
+                match self.peek() {
+                    Some(Tok::Close) => {
+                        self.pos += 1;
+                        Ok(expr)
+                    }
+                    _ => Err(self.error("`)' expected")),
+                }
+            }
+            _ => Err(self.error("operand expected")),
+        }
+    }
+}
+
+fn apply(op: &str, left: i64, right: i64) -> Result<i64, String> {
+    let value = match op {
+        "+" => left.wrapping_add(right),
+        "-" => left.wrapping_sub(right),
+        "*" => left.wrapping_mul(right),
+        "/" | "%" if right == 0 => return Err("division by 0".to_string()),
+        "/" => left.wrapping_div(right),
+        "%" => left.wrapping_rem(right),
+        "**" if right < 0 => return Err("exponent less than 0".to_string()),
+        "**" => left.wrapping_pow(right.min(u32::MAX as i64) as u32),
+        "<<" => left.wrapping_shl(right as u32),
+        ">>" => left.wrapping_shr(right as u32),
+        "&" => left & right,
+        "^" => left ^ right,
+        "|" => left | right,
+        "<" => (left < right) as i64,
+        ">" => (left > right) as i64,
+        "<=" => (left <= right) as i64,
+        ">=" => (left >= right) as i64,
+        "==" => (left == right) as i64,
+        "!=" => (left != right) as i64,
+        "," => right,
+        _ => return Err(format!("{}: unknown operator", op)),
+    };
+    Ok(value)
+}
+
+struct Evaluator<'a> {
+    shell: &'a mut Shell,
+    depth: usize,
+}
+
+impl Evaluator<'_> {
+    // A variable's value, which may itself be an expression. Unset and empty
+    // variables are 0.
+    fn var(&mut self, name: &str) -> Result<i64, String> {
+        let value = self.shell.get_var(name).unwrap_or("").trim().to_string();
+        if value.is_empty() {
+            return Ok(0);
+        }
+        if let Ok(value) = value.parse() {
+            return Ok(value);
+        }
+        if self.depth >= MAX_DEPTH {
+            return Err("expression recursion level exceeded".to_string());
+        }
+        self.depth += 1;
+        let result = self.eval_source(&value);
+        self.depth -= 1;
+        result.map_err(|message| format!("{}: {}", value, message))
+    }
+
+    fn eval_source(&mut self, src: &str) -> Result<i64, String> {
+        let mut parser = Parser {
+            src,
+            tokens: tokenize(src)?,
+            pos: 0,
+        };
+        if parser.tokens.is_empty() {
+            return Ok(0);
+        }
+        let expr = parser.comma()?;
+        if parser.pos < parser.tokens.len() {
+            return Err(parser.error("invalid arithmetic operator"));
+        }
+        self.eval(&expr)
+    }
+
+    fn eval(&mut self, expr: &Expr) -> Result<i64, String> {
+        match expr {
+            Expr::Num(value) => Ok(*value),
+            Expr::Var(name) => self.var(name),
+            Expr::Unary(op, operand) => {
+                let value = self.eval(operand)?;
+                Ok(match *op {
+                    "-" => value.wrapping_neg(),
+                    "!" => (value == 0) as i64,
+                    "~" => !value,
+                    _ => value,
+                })
+            }
+            Expr::Step(name, step, prefix) => {
+                let old = self.var(name)?;
+                let new = old.wrapping_add(*step);
+                self.shell.set_var(name, new.to_string());
+                Ok(if *prefix { new } else { old })
+            }
+            Expr::Binary("&&", left, right) => {
+                Ok((self.eval(left)? != 0 && self.eval(right)? != 0) as i64)
+            }
+            Expr::Binary("||", left, right) => {
+                Ok((self.eval(left)? != 0 || self.eval(right)? != 0) as i64)
+            }
+            Expr::Binary(op, left, right) => {
+                let left = self.eval(left)?;
+                let right = self.eval(right)?;
+                apply(op, left, right)
+            }
+            Expr::Cond(cond, then, otherwise) => {
+                if self.eval(cond)? != 0 {
+                    self.eval(then)
+                } else {
+                    self.eval(otherwise)
+                }
+            }
+            Expr::Assign(name, op, value) => {
+                let value = self.eval(value)?;
+                let value = match op {
+                    Some(op) => apply(op, self.var(name)?, value)?,
+                    None => value,
+                };
+                self.shell.set_var(name, value.to_string());
+                Ok(value)
+            }
+        }
+    }
+}
+
+// Evaluates an arithmetic expression. On failure the error message is
+// returned ready to be printed.
+pub fn eval(shell: &mut Shell, src: &str) -> Result<i64, String> {
+    let mut evaluator = Evaluator { shell, depth: 0 };
+    evaluator
+        .eval_source(src)
+        .map_err(|message| format!("{}: {}", src.trim(), message))
+}
@@ -0,0 +1,415 @@
+use std::fmt;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum WordPart {
+    Literal(String),
+    SingleQuoted(String),
+    Escaped(char),
+    DoubleQuoted(Vec<WordPart>),
+    Param(Param),
+    // The text of a `$(...)` or backquoted command.
+    CommandSub(String),
+    // The expression of a `$((...))`, before expansion.
+    Arith(Vec<WordPart>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplaceMode {
+    First,
+    All,
+    Prefix,
+    Suffix,
+}
+
+// What to do with a parameter's value. The flag on the default, assign,
+// error and alternative forms is set when written with a colon, so that an
+// empty value is treated like an unset one; on the removal forms it selects
+// the longest match.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamOp {
+    Plain,
+    Length,
+    Default(bool, Word),
+    Assign(bool, Word),
+    Error(bool, Word),
+    Alternative(bool, Word),
+    RemovePrefix(bool, Word),
+    RemoveSuffix(bool, Word),
+    Replace(ReplaceMode, Word, Word),
+    Substring(String, Option<String>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Param {
+    pub name: String,
+    pub index: Option<String>,
+    pub op: ParamOp,
+    // The expansion as written in the source.
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Word {
+    pub parts: Vec<WordPart>,
+}
+
+impl Word {
+    pub fn push_char(&mut self, c: char) {
+        push_literal(&mut self.parts, c);
+    }
+
+    // The word with all quoting removed.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for part in &self.parts {
+            part_text(part, &mut text);
+        }
+        text
+    }
+
+    // The word as it would be typed when it contains no quoting at all.
+    pub fn as_unquoted(&self) -> Option<&str> {
+        match self.parts.as_slice() {
+            [WordPart::Literal(s)] => Some(s),
+            _ => None,
+        }
+    }
+}
+
+pub fn push_literal(parts: &mut Vec<WordPart>, c: char) {
+    if let Some(WordPart::Literal(s)) = parts.last_mut() {
+        s.push(c);
+    } else {
+        parts.push(WordPart::Literal(c.to_string()));
+    }
+}
+
+fn part_text(part: &WordPart, text: &mut String) {
+    match part {
+        WordPart::Literal(s) | WordPart::SingleQuoted(s) => text.push_str(s),
+        WordPart::Escaped(c) => text.push(*c),
+        WordPart::DoubleQuoted(parts) => {
+            for part in parts {
+                part_text(part, text);
+            }
+        }
+        WordPart::Param(param) => text.push_str(&param.source),
+        WordPart::CommandSub(command) => {
+            text.push_str("$(");
+            text.push_str(command);
+            text.push(')');
+        }
+        WordPart::Arith(parts) => {
+            text.push_str("$((");
+            for part in parts {
+                part_text(part, text);
+            }
+            text.push_str("))");
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedirectOp {
+    Write,
+    // `>|`, which writes even under noclobber.
+    Clobber,
+    Append,
+    Read,
+    ReadWrite,
+    DupOut,
+    DupIn,
+    WriteAll,
+    AppendAll,
+    HereString,
+    HereDoc,
+}
+
+impl RedirectOp {
+    pub fn symbol(&self) -> &'static str {
+        match self {
+            RedirectOp::Write => ">",
+            RedirectOp::Clobber => ">|",
+            RedirectOp::Append => ">>",
+            RedirectOp::Read => "<",
+            RedirectOp::ReadWrite => "<>",
+            RedirectOp::DupOut => ">&",
+            RedirectOp::DupIn => "<&",
+            RedirectOp::WriteAll => "&>",
+            RedirectOp::AppendAll => "&>>",
+            RedirectOp::HereString => "<<<",
+            RedirectOp::HereDoc => "<<",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Redirect {
+    pub fd: Option<i32>,
+    pub op: RedirectOp,
+    pub target: Word,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub name: String,
+    pub value: Word,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SimpleCommand {
+    pub assignments: Vec<Assignment>,
+    pub words: Vec<Word>,
+    pub redirects: Vec<Redirect>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CaseItem {
+    pub patterns: Vec<Word>,
+    pub body: List,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompoundCommand {
+    // `{ list; }`
+    Group(List),
+    // `( list )`
+    Subshell(List),
+    // Each `if`/`elif` condition with its body, then the `else` body.
+    If(Vec<(List, List)>, Option<List>),
+    // `while` or, when the flag is set, `until`.
+    While(bool, List, List),
+    // `for name [in words]`; without `in` the positional parameters are used.
+    For(String, Option<Vec<Word>>, List),
+    // `for ((init; condition; step))`
+    ArithFor(Word, List),
+    Case(Word, Vec<CaseItem>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Simple(SimpleCommand),
+    // `((expression))`
+    Arith(Word),
+    Compound(CompoundCommand, Vec<Redirect>),
+    // A function definition and its body.
+    Function(String, Rc<Command>),
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pipeline {
+    pub commands: Vec<Command>,
+    // Started with `!`, which inverts its status.
+    pub negated: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Connector {
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct AndOr {
+    pub first: Pipeline,
+    pub rest: Vec<(Connector, Pipeline)>,
+    // Set when the list ends with `&`.
+    pub background: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct List {
+    pub items: Vec<AndOr>,
+}
+
+// Commands are displayed the way they could have been typed, for the job
+// table. Here-documents only show their operator.
+
+fn write_parts(f: &mut fmt::Formatter, parts: &[WordPart], quoted: bool) -> fmt::Result {
+    for part in parts {
+        match part {
+            WordPart::Literal(s) if quoted => {
+                for c in s.chars() {
+                    if matches!(c, '"' | '\\' | '$' | '`') {
+                        write!(f, "\\")?;
+                    }
+                    write!(f, "{}", c)?;
+                }
+            }
+            WordPart::Literal(s) => write!(f, "{}", s)?,
+            WordPart::SingleQuoted(s) => write!(f, "'{}'", s)?,
+            WordPart::Escaped(c) => write!(f, "\\{}", c)?,
+            WordPart::DoubleQuoted(parts) => {
+                write!(f, "\"")?;
+                write_parts(f, parts, true)?;
+                write!(f, "\"")?;
+            }
+            WordPart::Param(param) => write!(f, "{}", param.source)?,
+            WordPart::CommandSub(command) => write!(f, "$({})", command)?,
+            WordPart::Arith(parts) => {
+                write!(f, "$((")?;
+                write_parts(f, parts, false)?;
+                write!(f, "))")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+impl fmt::Display for Word {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write_parts(f, &self.parts, false)
+    }
+}
+
+impl fmt::Display for Redirect {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(fd) = self.fd {
+            write!(f, "{}", fd)?;
+        }
+        match self.op {
+            RedirectOp::HereDoc => write!(f, "{}", self.op.symbol()),
+            _ => write!(f, "{}{}", self.op.symbol(), self.target),
+        }
+    }
+}
+
+fn write_redirects(f: &mut fmt::Formatter, redirects: &[Redirect]) -> fmt::Result {
+    for redirect in redirects {
+        write!(f, " {}", redirect)?;
+    }
+    Ok(())
+}
+
+// A list inside a compound command, terminated so that a closing word can
+// follow it.
+struct Body<'a>(&'a List);
+
+impl fmt::Display for Body<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)?;
+        match self.0.items.last() {
+            Some(and_or) if and_or.background => Ok(()),
+            _ => write!(f, ";"),
+        }
+    }
+}
+
+impl fmt::Display for CompoundCommand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompoundCommand::Group(list) => write!(f, "{{ {} }}", Body(list)),
+            CompoundCommand::Subshell(list) => write!(f, "( {} )", list),
+            CompoundCommand::If(branches, otherwise) => {
+                for (i, (condition, body)) in branches.iter().enumerate() {
+                    let keyword = if i == 0 { "if" } else { "elif" };
+                    write!(f, "{} {} then {} ", keyword, Body(condition), Body(body))?;
+                }
+                if let Some(body) = otherwise {
+                    write!(f, "else {} ", Body(body))?;
+                }
+                write!(f, "fi")
+            }
+            CompoundCommand::While(until, condition, body) => {
+                let keyword = if *until { "until" } else { "while" };
+                write!(f, "{} {} do {} done", keyword, Body(condition), Body(body))
+            }
+            CompoundCommand::For(name, words, body) => {
+                write!(f, "for {}", name)?;
+                if let Some(words) = words {
+                    write!(f, " in")?;
+                    for word in words {
+                        write!(f, " {}", word)?;
+                    }
+                }
+                write!(f, "; do {} done", Body(body))
+            }
+            CompoundCommand::ArithFor(header, body) => {
+                write!(f, "for (({})); do {} done", header, Body(body))
+            }
+            CompoundCommand::Case(word, items) => {
+                write!(f, "case {} in", word)?;
+                for item in items {
+                    let patterns: Vec<String> = item.patterns.iter().map(Word::to_string).collect();
+                    write!(f, " {}) {};;", patterns.join(" | "), item.body)?;
+                }
+                write!(f, " esac")
+            }
+        }
+    }
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Simple(simple) => {
+                let mut words = simple
+                    .assignments
+                    .iter()
+                    .map(|assignment| format!("{}={}", assignment.name, assignment.value))
+                    .chain(simple.words.iter().map(Word::to_string))
+                    .chain(simple.redirects.iter().map(Redirect::to_string));
+                if let Some(first) = words.next() {
+                    write!(f, "{}", first)?;
+                }
+                for word in words {
+                    write!(f, " {}", word)?;
+                }
+                Ok(())
+            }
+            Command::Arith(word) => write!(f, "(({}))", word),
+            Command::Compound(compound, redirects) => {
+                write!(f, "{}", compound)?;
+                write_redirects(f, redirects)
+            }
+            Command::Function(name, body) => write!(f, "{} () {}", name, body),
+        }
+    }
+}
+
+impl fmt::Display for Pipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.negated {
+            write!(f, "! ")?;
+        }
+        for (i, command) in self.commands.iter().enumerate() {
+            if i > 0 {
+                write!(f, " | ")?;
+            }
+            write!(f, "{}", command)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for AndOr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        for (connector, pipeline) in &self.rest {
+            let symbol = match connector {
+                Connector::And => "&&",
+                Connector::Or => "||",
+            };
+            write!(f, " {} {}", symbol, pipeline)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for List {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, and_or) in self.items.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}", and_or)?;
+            if and_or.background {
+                write!(f, " &")?;
+            } else if i + 1 < self.items.len() {
+                write!(f, ";")?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+// Brace expansion, which runs before every other expansion and only looks at
+// unquoted braces and commas.
+
+use crate::ast::{Word, WordPart};
+
+// A word broken into its unquoted characters and everything else, which
+// passes through brace expansion untouched.
+#[derive(Clone)]
+enum Item {
+    Char(char),
+    Part(WordPart),
+}
+
+fn items(word: &Word) -> Vec<Item> {
+    let mut items = Vec::new();
+    for part in &word.parts {
+        match part {
+            WordPart::Literal(text) => items.extend(text.chars().map(Item::Char)),
+            part => items.push(Item::Part(part.clone())),
+        }
+    }
+    items
+}
+
+fn to_word(items: &[Item]) -> Word {
+    let mut word = Word::default();
+    for item in items {
+        match item {
+            Item::Char(c) => word.push_char(*c),
+            Item::Part(part) => word.parts.push(part.clone()),
+        }
+    }
+    word
+}
+
+fn is_char(item: &Item, expected: char) -> bool {
+    matches!(item, Item::Char(c) if *c == expected)
+}
+
+// Finds the `}` matching the `{` at the start of `items`, along with the
+// positions of the commas between them that are not nested any deeper.
+fn closing(items: &[Item]) -> Option<(usize, Vec<usize>)> {
+    let mut depth = 0;
+    let mut commas = Vec::new();
+
+    for (i, item) in items.iter().enumerate() {
+        if is_char(item, '{') {
+            depth += 1;
+        } else if is_char(item, '}') {
+            depth -= 1;
+            if depth == 0 {
+                return Some((i, commas));
+            }
+        } else if depth == 1 && is_char(item, ',') {
+            commas.push(i);
+        }
+    }
+
+    None
+}
+
+fn integer_bound(text: &str) -> Option<(i64, usize)> {
+    let value = text.parse().ok()?;
+    let digits = text.trim_start_matches(['-', '+']);
+    let width = if digits.len() > 1 && digits.starts_with('0') {
+        text.len()
+    } else {
+        0
+    };
+    Some((value, width))
+}
+
+fn char_bound(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+        _ => None,
+    }
+}
+
+// The most words a sequence expression may produce; a longer one is left as
+// it is written.
+const MAX_SEQUENCE: u64 = 1_000_000;
+
+fn steps(start: i64, end: i64, incr: i64) -> Option<Vec<i64>> {
+    let incr = incr.unsigned_abs().max(1);
+    if start.abs_diff(end) / incr >= MAX_SEQUENCE {
+        return None;
+    }
+
+    let mut values = Vec::new();
+    let mut value = Some(start);
+    while let Some(current) = value {
+        if (start <= end && current > end) || (start > end && current < end) {
+            break;
+        }
+        values.push(current);
+        value = if start <= end {
+            current.checked_add_unsigned(incr)
+        } else {
+            current.checked_sub_unsigned(incr)
+        };
+    }
+
+    Some(values)
+}
+
+// Expands a sequence expression such as `1..10`, `01..20..2` or `a..z`.
+fn sequence(text: &str) -> Option<Vec<String>> {
+    let fields: Vec<&str> = text.split("..").collect();
+    let incr = match fields.len() {
+        2 => 1,
+        3 => fields[2].parse().ok()?,
+        _ => return None,
+    };
+
+    if let (Some((start, start_width)), Some((end, end_width))) =
+        (integer_bound(fields[0]), integer_bound(fields[1]))
+    {
+        let width = start_width.max(end_width);
+        let values = steps(start, end, incr)?.into_iter().map(|value| {
+            if value < 0 {
+                let digits = value.unsigned_abs();
+                format!("-{:0width$}", digits, width = width.saturating_sub(1))
+            } else {
+                format!("{:0width$}", value, width = width)
+            }
+        });
+        return Some(values.collect());
+    }
+
+    let (start, end) = (char_bound(fields[0])?, char_bound(fields[1])?);
+    let values = steps(start as i64, end as i64, incr)?
+        .into_iter()
+        .filter_map(|value| char::from_u32(value as u32))
+        .map(String::from);
+    Some(values.collect())
+}
+
+fn expand_items(items: &[Item]) -> Vec<Vec<Item>> {
+    for open in 0..items.len() {
+        if !is_char(&items[open], '{') {
+            continue;
+        }
+        let (close, commas) = match closing(&items[open..]) {
+            Some((close, commas)) => (open + close, commas),
+            None => continue,
+        };
+
+        let prefix = &items[..open];
+        let suffix = expand_items(&items[close + 1..]);
+
+        let alternatives: Vec<Vec<Item>> = if commas.is_empty() {
+            let inner: Option<String> = items[open + 1..close]
+                .iter()
+                .map(|item| match item {
+                    Item::Char(c) => Some(*c),
+                    Item::Part(_) => None,
+                })
+                .collect();
+            match inner.as_deref().and_then(sequence) {
+                Some(values) => values
+                    .into_iter()
+                    .map(|value| value.chars().map(Item::Char).collect())
+                    .collect(),
+                None => continue,
+            }
+        } else {
+            let mut bounds = vec![open];
+            bounds.extend(commas.iter().map(|comma| open + comma));
+            bounds.push(close);
+            bounds
+                .windows(2)
+                .flat_map(|pair| expand_items(&items[pair[0] + 1..pair[1]]))
+                .collect()
+        };
+
+        let mut words = Vec::new();
+        for alternative in &alternatives {
+            for rest in &suffix {
+                let mut word = prefix.to_vec();
+                word.extend(alternative.iter().cloned());
+                word.extend(rest.iter().cloned());
+                words.push(word);
+            }
+        }
+        return words;
+    }
+
+    vec![items.to_vec()]
+}
+
+// Expands the braces in a word into the words they stand for.
+pub fn expand(word: &Word) -> Vec<Word> {
+    if !word
+        .parts
+        .iter()
+        .any(|part| matches!(part, WordPart::Literal(text) if text.contains('{')))
+    {
+        return vec![word.clone()];
+    }
+    expand_items(&items(word))
+        .iter()
+        .map(|items| to_word(items))
+        .collect()
+}
@@ -1,18 +1,38 @@
+use std::collections::HashMap;
 use std::env;
-use std::fs;
-use std::io::Write;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+
+use nix::errno::Errno;
+use nix::sys::signal::Signal;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{fork, ForkResult, Pid};
 
-use crate::input;
+use crate::arith;
+use crate::ast::{
+    AndOr, Assignment, CaseItem, Command as AstCommand, CompoundCommand, Connector, List, Pipeline,
+    SimpleCommand, Word, WordPart,
+};
+use crate::expand;
+use crate::input::{self, Source};
+use crate::jobs::{self, Group, Job, Process, State};
+use crate::lexer;
+use crate::redirect::{self, Streams};
+use crate::shell::{Flow, Options, Shell, Shopts, Var};
+use crate::signals::{self, Trap};
 use crate::ShellCompleter;
 
-use input::OutputConf;
-use input::OutputMode;
 use rustyline::history::DefaultHistory;
 use rustyline::Editor;
 
 enum OutputMsgType {
     StdOut,
     StdErr,
+    // Sets the builtin's exit status without printing anything.
+    Status(i32),
 }
 
 struct OutputMsg {
@@ -22,368 +42,2157 @@ struct OutputMsg {
 
 fn msg(message: String) -> OutputMsg {
     OutputMsg {
-        message: message,
+        message,
         msg_type: OutputMsgType::StdOut,
     }
 }
 fn err(message: String) -> OutputMsg {
     OutputMsg {
-        message: message,
+        message,
         msg_type: OutputMsgType::StdErr,
     }
 }
 
-fn cmd_echo(args: &Vec<String>) -> Option<OutputMsg> {
-    return Some(msg(args.join(" ")));
+fn status(code: i32) -> OutputMsg {
+    OutputMsg {
+        message: String::new(),
+        msg_type: OutputMsgType::Status(code),
+    }
+}
+
+fn cmd_echo(args: &[String]) -> Option<OutputMsg> {
+    Some(msg(args.join(" ")))
 }
 
-fn cmd_type(arg: &String, builtin: &Vec<String>) -> Option<OutputMsg> {
+fn cmd_type(arg: &String, shell: &Shell) -> Option<OutputMsg> {
     if arg.is_empty() {
         return None;
     }
 
-    if builtin.contains(&arg) {
-        return Some(msg(format!("{} is a shell builtin", arg)));
+    if let Some(value) = shell.aliases.get(arg) {
+        return Some(msg(format!("{} is aliased to `{}'", arg, value)));
     }
 
-    if let Ok(path_var) = env::var("PATH") {
-        let path_entries = env::split_paths(&path_var);
+    if shell.functions.contains_key(arg) {
+        return Some(msg(format!("{} is a function", arg)));
+    }
 
-        for dir in path_entries {
-            let full_path = dir.join(&arg);
-            if full_path.exists() {
-                return Some(msg(format!("{} is {}", arg, full_path.display())));
-            }
-        }
-    } else {
-        return Some(err("failed to get path variable".to_string()));
+    if shell.builtin.contains(arg) {
+        return Some(msg(format!("{} is a shell builtin", arg)));
     }
 
-    return Some(err(format!("{}: not found", arg)));
+    match shell.get_var("PATH") {
+        Some(path_var) => match find_in_path(arg, path_var) {
+            Some(full_path) => Some(msg(format!("{} is {}", arg, full_path.display()))),
+            None => Some(err(format!("{}: not found", arg))),
+        },
+        None => Some(err("failed to get path variable".to_string())),
+    }
 }
 
-fn cmd_pwd() -> Option<OutputMsg> {
-    return Some(msg(format!("{}", env::current_dir().unwrap().display())));
+fn find_in_path(name: &str, path_var: &str) -> Option<PathBuf> {
+    env::split_paths(path_var)
+        .map(|dir| dir.join(name))
+        .find(|full_path| full_path.exists())
 }
 
-fn cmd_cd(args: &Vec<String>) -> Option<OutputMsg> {
-    let args_len = args.len();
-
-    if args_len == 0 {
-        return None;
-    }
-    if args_len > 1 {
-        return Some(err("cd: too many arguments".to_string()));
-    }
-    let path = match env::home_dir() {
-        Some(home_path) => {
-            let home_str = home_path.to_str().unwrap_or("");
-            args[0].replace("~", home_str) // returns a new string
-        }
-        None => {
-            eprintln!("could not determine the home directory.");
-            args[0].to_string() // still return something
+// Quotes a value the way `export -p` prints it, so it can be read back.
+fn declare_quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        if matches!(c, '"' | '\\' | '$' | '`') {
+            quoted.push('\\');
         }
-    };
-    let new_dir = std::path::Path::new(&path);
-    match env::set_current_dir(new_dir) {
-        Ok(_) => {}
-        Err(_) => return Some(err(format!("cd: {}: No such file or directory", path))),
-    };
-    return None;
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
 }
-use std::fmt::Write as FmtWrite;
 
-use std::fs::{File, OpenOptions};
-use std::io::{BufRead, BufReader};
+// Lists the variables passed to the commands the shell starts, including
+// those assigned for `env` alone, as in `FOO=bar env`.
+fn cmd_env(shell: &Shell) -> Vec<Option<OutputMsg>> {
+    shell
+        .environment()
+        .into_iter()
+        .map(|(name, value)| Some(msg(format!("{}={}", name, value))))
+        .collect()
+}
 
-fn delete_header(filename: &str) -> std::io::Result<()> {
-    let file = File::open(filename)?;
-    let reader = BufReader::new(file);
+fn cmd_export(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let mut outputs = Vec::new();
+    let mut unexport = false;
+    let mut names = args;
 
-    let lines: Vec<String> = reader
-        .lines()
-        .filter_map(Result::ok)
-        .filter(|line| line.trim() != "#V2")
-        .collect();
+    while let Some((arg, rest)) = names.split_first() {
+        if arg == "--" {
+            names = rest;
+            break;
+        }
+        if !arg.starts_with('-') || arg.len() < 2 {
+            break;
+        }
+        for c in arg.chars().skip(1) {
+            match c {
+                'n' => unexport = true,
+                'p' => {}
+                _ => {
+                    outputs.push(Some(err(format!("export: -{}: invalid option", c))));
+                    outputs.push(Some(err(
+                        "export: usage: export [-n] [name[=value] ...] or export -p".to_string(),
+                    )));
+                    return outputs;
+                }
+            }
+        }
+        names = rest;
+    }
 
-    let mut file = OpenOptions::new()
-        .write(true)
-        .truncate(true)
-        .open(filename)?;
+    if names.is_empty() {
+        let mut exported: Vec<_> = shell.vars.iter().filter(|(_, var)| var.exported).collect();
+        exported.sort_by(|a, b| a.0.cmp(b.0));
 
-    for line in lines {
-        writeln!(file, "{}", line)?;
+        let listing: Vec<String> = exported
+            .into_iter()
+            .map(|(name, var)| match &var.value {
+                Some(value) => format!("declare -x {}={}", name, declare_quote(value)),
+                None => format!("declare -x {}", name),
+            })
+            .collect();
+        if !listing.is_empty() {
+            outputs.push(Some(msg(listing.join("\n"))));
+        }
+        return outputs;
     }
 
-    Ok(())
+    for arg in names {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !lexer::is_name(name) {
+            outputs.push(Some(err(format!(
+                "export: `{}': not a valid identifier",
+                arg
+            ))));
+            continue;
+        }
+        if let Some(value) = value {
+            shell.set_var(name, value.to_string());
+        }
+        shell.set_exported(name, !unexport);
+    }
+
+    outputs
 }
 
-fn cmd_history(
-    rl: &mut Editor<ShellCompleter, DefaultHistory>,
-    args: &Vec<String>,
-) -> Option<OutputMsg> {
-    let mut history = String::new();
+fn cmd_unset(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let mut outputs = Vec::new();
+    let mut functions = false;
+    let mut names = args;
 
-    let history_len = rl.history().iter().count();
+    while let Some((arg, rest)) = names.split_first() {
+        match arg.as_str() {
+            "--" => {
+                names = rest;
+                break;
+            }
+            "-v" => functions = false,
+            "-f" => functions = true,
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                outputs.push(Some(err(format!("unset: {}: invalid option", arg))));
+                outputs.push(Some(err(
+                    "unset: usage: unset [-f] [-v] [name ...]".to_string()
+                )));
+                return outputs;
+            }
+            _ => break,
+        }
+        names = rest;
+    }
 
-    let mut n: usize = history_len;
-    if !args.is_empty() {
-        if args[0] == "-r" {
-            rl.load_history(&args[1]).ok();
-            return None;
-        } else if args[0] == "-w" {
-            rl.save_history(&args[1]).ok();
-            delete_header(&args[1]).unwrap();
-            return None;
-        } else if args[0] == "-a" {
-            rl.append_history(&args[1]).ok();
-            delete_header(&args[1]).unwrap();
-            return None;
-        } else {
-            n = args[0].parse().expect("Not a valid number");
+    for name in names {
+        if !lexer::is_name(name) {
+            outputs.push(Some(err(format!(
+                "unset: `{}': not a valid identifier",
+                name
+            ))));
+            continue;
+        }
+        // Without `-f`, a function is only removed when there is no
+        // variable of that name.
+        if functions || !shell.vars.contains_key(name.as_str()) {
+            shell.functions.remove(name.as_str());
+        }
+        if !functions {
+            shell.unset_var(name);
         }
     }
 
-    let start = if history_len > n { history_len - n } else { 0 };
+    outputs
+}
 
-    for (i, entry) in rl.history().iter().skip(start).enumerate() {
-        writeln!(&mut history, "\t{} {}", start + i + 1, entry).unwrap();
-    }
-    Some(msg(history.trim_end().to_string()))
+fn alias_definition(name: &str, value: &str) -> String {
+    format!("alias {}='{}'", name, value.replace('\'', "'\\''"))
 }
 
-fn cmd_run(cmd: &str, args: &Vec<String>) -> Vec<Option<OutputMsg>> {
-    let mut command = std::process::Command::new(cmd);
-    command.args(args);
+fn cmd_alias(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let mut outputs = Vec::new();
+    let names = match args.split_first() {
+        Some((flag, rest)) if flag == "-p" => rest,
+        _ => args,
+    };
 
-    match command.output() {
-        Ok(output) => {
-            let mut result = Vec::new();
+    if names.is_empty() {
+        let mut aliases: Vec<_> = shell.aliases.iter().collect();
+        aliases.sort();
+        for (name, value) in aliases {
+            outputs.push(Some(msg(alias_definition(name, value))));
+        }
+        return outputs;
+    }
 
-            let stdout = String::from_utf8_lossy(&output.stdout).trim().to_string();
-            if !stdout.is_empty() {
-                result.push(Some(msg(stdout)));
+    for arg in names {
+        match arg.split_once('=') {
+            Some((name, _))
+                if name.is_empty()
+                    || name
+                        .contains(|c: char| c.is_whitespace() || "/$`\\'\"|&;()<>".contains(c)) =>
+            {
+                outputs.push(Some(err(format!("alias: `{}': invalid alias name", name))));
             }
-
-            let stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
-            if !stderr.is_empty() {
-                result.push(Some(err(stderr)));
+            Some((name, value)) => {
+                shell.aliases.insert(name.to_string(), value.to_string());
             }
+            None => match shell.aliases.get(arg) {
+                Some(value) => outputs.push(Some(msg(alias_definition(arg, value)))),
+                None => outputs.push(Some(err(format!("alias: {}: not found", arg)))),
+            },
+        }
+    }
+
+    outputs
+}
 
-            result
+fn cmd_unalias(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    match args.first().map(String::as_str) {
+        Some("-a") => {
+            shell.aliases.clear();
+            return Vec::new();
+        }
+        None => {
+            return vec![
+                Some(err(
+                    "unalias: usage: unalias [-a] name [name ...]".to_string()
+                )),
+                Some(status(2)),
+            ]
         }
-        Err(_) => {
-            vec![Some(err(format!("{}: command not found", cmd)))]
+        _ => {}
+    }
+
+    let mut outputs = Vec::new();
+    for name in args {
+        if shell.aliases.remove(name).is_none() {
+            outputs.push(Some(err(format!("unalias: {}: not found", name))));
         }
     }
+    outputs
 }
 
-fn write_to_file(path: &String, value: String) -> std::io::Result<()> {
-    let mut file = fs::File::create(path)?;
+fn trap_definition(trap: Trap, action: &str) -> String {
+    format!(
+        "trap -- '{}' {}",
+        action.replace('\'', "'\\''"),
+        trap.name()
+    )
+}
 
-    file.write_all(value.as_bytes())?;
+fn cmd_trap(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let mut outputs = Vec::new();
+    let (flag, operands) = match args.split_first() {
+        Some((first, rest)) if first == "--" => (None, rest),
+        Some((first, rest)) if first == "-l" || first == "-p" => (Some(first.as_str()), rest),
+        _ => (None, args),
+    };
 
-    Ok(())
-}
+    if flag == Some("-l") {
+        let names: Vec<String> = Signal::iterator()
+            .map(|signal| format!("{:2}) {}", signal as i32, signal.as_str()))
+            .collect();
+        for row in names.chunks(5) {
+            outputs.push(Some(msg(row.join("\t"))));
+        }
+        return outputs;
+    }
 
-fn append_to_file(path: &String, value: String) -> std::io::Result<()> {
-    let mut file = fs::OpenOptions::new()
-        .append(true)
-        .create(true)
-        .open(path)?;
+    if flag == Some("-p") || operands.is_empty() {
+        let mut traps: Vec<Trap> = Vec::new();
+        for spec in operands {
+            match Trap::parse(spec) {
+                Some(trap) => traps.push(trap),
+                None => outputs.push(Some(err(format!(
+                    "trap: {}: invalid signal specification",
+                    spec
+                )))),
+            }
+        }
+        let listed = shell.parent_traps.as_ref().unwrap_or(&shell.traps);
+        if operands.is_empty() {
+            traps = listed.keys().copied().collect();
+        }
+        for trap in traps {
+            if let Some(action) = listed.get(&trap) {
+                outputs.push(Some(msg(trap_definition(trap, action))));
+            }
+        }
+        return outputs;
+    }
 
-    file.write_all(value.as_bytes())?;
+    // A lone condition, or a number in place of the action, resets.
+    let (action, specs) = match operands {
+        [_] => (None, operands),
+        [first, ..] if first.parse::<u32>().is_ok() => (None, operands),
+        [first, rest @ ..] if first == "-" => (None, rest),
+        [first, rest @ ..] => (Some(first.clone()), rest),
+        [] => (None, operands),
+    };
+    for spec in specs {
+        match Trap::parse(spec) {
+            Some(trap) => signals::set_trap(shell, trap, action.clone()),
+            None => outputs.push(Some(err(format!(
+                "trap: {}: invalid signal specification",
+                spec
+            )))),
+        }
+    }
 
-    Ok(())
+    outputs
 }
 
-fn output_handler(outputs: Vec<Option<OutputMsg>>, output_conf: OutputConf) {
-    if output_conf.std_out != "" {
-        append_to_file(&output_conf.std_out, "".to_string()).unwrap();
-    }
-    if output_conf.std_err != "" {
-        append_to_file(&output_conf.std_err, "".to_string()).unwrap();
-    }
-    for output in outputs {
-        match output {
-            Some(value) => match value.msg_type {
-                OutputMsgType::StdOut => match output_conf.std_out_mode {
-                    OutputMode::Default => {
-                        println!("{}", value.message);
-                    }
-                    OutputMode::File => {
-                        write_to_file(&output_conf.std_out, value.message + "\n").unwrap();
-                    }
-                    OutputMode::FileAppend => {
-                        append_to_file(&output_conf.std_out, value.message + "\n").unwrap();
-                    }
-                },
-                OutputMsgType::StdErr => match output_conf.std_err_mode {
-                    OutputMode::Default => {
-                        eprintln!("{}", value.message);
-                    }
-                    OutputMode::File => {
-                        write_to_file(&output_conf.std_err, value.message + "\n").unwrap();
-                    }
-                    OutputMode::FileAppend => {
-                        append_to_file(&output_conf.std_err, value.message + "\n").unwrap();
-                    }
-                },
-            },
-            None => {}
-        }
+// `shopt -o` works on the options of `set` instead.
+fn shopt_option<'a>(shell: &'a mut Shell, set_options: bool, name: &str) -> Option<&'a mut bool> {
+    match set_options {
+        true => shell.options.get_mut(name),
+        false => shell.shopts.get_mut(name),
     }
 }
 
-fn run_builtin(
-    cmd: &str,
-    args: &Vec<String>,
-    builtin: &Vec<String>,
-    rl: &mut Editor<ShellCompleter, DefaultHistory>,
-) -> Vec<Option<OutputMsg>> {
+fn cmd_shopt(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
     let mut outputs = Vec::new();
-    match cmd {
-        "exit" => {
-            let histfile = std::env::var("HISTFILE").ok();
+    let mut set = None;
+    let mut print = false;
+    let mut quiet = false;
+    let mut set_options = false;
+    let mut names = args;
 
-            if let Some(path) = histfile {
-                rl.save_history(&path).ok();
-                delete_header(&path).unwrap();
+    while let Some((arg, rest)) = names.split_first() {
+        if !arg.starts_with('-') || arg.len() < 2 {
+            break;
+        }
+        for c in arg.chars().skip(1) {
+            match c {
+                's' => set = Some(true),
+                'u' => set = Some(false),
+                'p' => print = true,
+                'q' => quiet = true,
+                'o' => set_options = true,
+                _ => {
+                    outputs.push(Some(err(format!("shopt: -{}: invalid option", c))));
+                    outputs.push(Some(err(
+                        "shopt: usage: shopt [-pqsuo] [optname ...]".to_string()
+                    )));
+                    return outputs;
+                }
             }
-            std::process::exit(0);
         }
-        "echo" => {
-            outputs.push(cmd_echo(args));
+        names = rest;
+    }
+
+    for name in names {
+        if shopt_option(shell, set_options, name).is_none() {
+            outputs.push(Some(err(format!(
+                "shopt: {}: invalid shell option name",
+                name
+            ))));
         }
-        "type" => {
-            for arg in args {
-                outputs.push(cmd_type(arg, builtin));
+    }
+    if !outputs.is_empty() {
+        return outputs;
+    }
+
+    if let (Some(value), false) = (set, names.is_empty()) {
+        for name in names {
+            if let Some(option) = shopt_option(shell, set_options, name) {
+                *option = value;
             }
         }
-        "pwd" => {
-            outputs.push(cmd_pwd());
-        }
-        "cd" => {
-            outputs.push(cmd_cd(args));
+        return outputs;
+    }
+
+    let listed: Vec<&str> = if names.is_empty() && set_options {
+        Options::NAMES.to_vec()
+    } else if names.is_empty() {
+        Shopts::NAMES.to_vec()
+    } else {
+        names.iter().map(String::as_str).collect()
+    };
+
+    let mut all_on = true;
+    let mut lines = Vec::new();
+    for name in listed {
+        let on = shopt_option(shell, set_options, name).is_some_and(|option| *option);
+        if set.is_some_and(|value| value != on) {
+            continue;
         }
-        "history" => {
-            outputs.push(cmd_history(rl, args));
+        all_on &= on;
+        if print && set_options {
+            lines.push(format!("set {}o {}", if on { "-" } else { "+" }, name));
+        } else if print {
+            lines.push(format!("shopt {} {}", if on { "-s" } else { "-u" }, name));
+        } else {
+            lines.push(format!("{:<15}\t{}", name, if on { "on" } else { "off" }));
         }
-        _ => {}
     }
 
+    if !quiet && !lines.is_empty() {
+        outputs.push(Some(msg(lines.join("\n"))));
+    }
+    if !all_on && !names.is_empty() {
+        outputs.push(Some(status(1)));
+    }
     outputs
 }
 
-use std::os::unix::io::RawFd;
+fn set_usage() -> OutputMsg {
+    err("set: usage: set [-efuxC] [-o option-name] [--] [-] [arg ...]".to_string())
+}
+
+fn cmd_set(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let mut outputs = Vec::new();
+    if args.is_empty() {
+        let mut vars: Vec<(&String, &str)> = shell
+            .vars
+            .iter()
+            .filter_map(|(name, var)| Some((name, var.value.as_deref()?)))
+            .collect();
+        vars.sort();
+        for (name, value) in vars {
+            outputs.push(Some(msg(format!("{}={}", name, trace_quote(value)))));
+        }
+        return outputs;
+    }
 
-fn write_outputs_to_fd(outputs: Vec<Option<OutputMsg>>, out_fd: RawFd) {
-    let mut writer = unsafe { std::fs::File::from_raw_fd(out_fd) };
+    let mut rest = args;
+    let mut positional = false;
+    while let Some((arg, after)) = rest.split_first() {
+        let on = match arg.chars().next() {
+            Some('-') => true,
+            Some('+') => false,
+            _ => {
+                positional = true;
+                break;
+            }
+        };
+        rest = after;
+        if arg == "--" || arg == "-" {
+            positional = arg == "--" || !rest.is_empty();
+            break;
+        }
 
-    for output in outputs {
-        if let Some(msg) = output {
-            writeln!(writer, "{}", msg.message).unwrap();
+        for flag in arg.chars().skip(1) {
+            let name = match flag {
+                'o' => match rest.split_first() {
+                    Some((name, after)) => {
+                        rest = after;
+                        name.as_str()
+                    }
+                    None => {
+                        outputs.extend(option_listing(shell, on));
+                        continue;
+                    }
+                },
+                _ => match Options::flag(flag) {
+                    Some(name) => name,
+                    None => {
+                        outputs.push(Some(err(format!(
+                            "set: {}{}: invalid option",
+                            arg.chars().next().unwrap_or('-'),
+                            flag
+                        ))));
+                        outputs.push(Some(set_usage()));
+                        outputs.push(Some(status(2)));
+                        return outputs;
+                    }
+                },
+            };
+            match shell.options.get_mut(name) {
+                Some(option) => *option = on,
+                None => {
+                    outputs.push(Some(err(format!("set: {}: invalid option name", name))));
+                    outputs.push(Some(status(2)));
+                    return outputs;
+                }
+            }
         }
     }
+
+    if positional {
+        shell.positional = rest.to_vec();
+    }
+    outputs
 }
 
-use nix::unistd::{close, pipe};
-use std::os::unix::io::FromRawFd;
-use std::process::{Command, Stdio};
+// `set -o` shows the options as a table, `set +o` as the commands that
+// would set them again.
+fn option_listing(shell: &Shell, table: bool) -> Vec<Option<OutputMsg>> {
+    Options::NAMES
+        .iter()
+        .map(|name| {
+            let on = shell.options.get(name);
+            let line = match table {
+                true => format!("{:<15}\t{}", name, if on { "on" } else { "off" }),
+                false => format!("set {}o {}", if on { "-" } else { "+" }, name),
+            };
+            Some(msg(line))
+        })
+        .collect()
+}
 
-pub fn run_pipeline(
-    cmds: Vec<String>,
-    args: Vec<Vec<String>>,
-    builtin: &Vec<String>,
-    rl: &mut Editor<ShellCompleter, DefaultHistory>,
-) {
-    let mut children = Vec::new();
-    let mut prev_read: Option<RawFd> = None;
+fn cmd_let(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    if args.is_empty() {
+        return vec![Some(err("let: expression expected".to_string()))];
+    }
 
-    for i in 0..cmds.len() {
-        let is_last = i == cmds.len() - 1;
+    let mut last = 0;
+    for arg in args {
+        match arith::eval(shell, arg) {
+            Ok(value) => last = value,
+            Err(message) => return vec![Some(err(format!("let: {}", message)))],
+        }
+    }
 
-        let (read_fd, write_fd) = if !is_last {
-            let (r, w) = pipe().expect("pipe failed");
-            (Some(r), Some(w))
-        } else {
-            (None, None)
-        };
+    vec![Some(status((last == 0) as i32))]
+}
 
-        let cmd_name = &cmds[i];
-        let filtered_args: Vec<String> = args[i]
-            .iter()
-            .filter(|arg| !arg.is_empty())
-            .cloned()
-            .collect();
+// `break` and `continue`, which leave or restart the innermost `levels`
+// enclosing loops.
+fn cmd_loop_control(shell: &mut Shell, cmd: &str, args: &[String]) -> Vec<Option<OutputMsg>> {
+    if shell.loop_depth == 0 {
+        return vec![
+            Some(err(format!(
+                "{}: only meaningful in a `for', `while', or `until' loop",
+                cmd
+            ))),
+            Some(status(0)),
+        ];
+    }
 
-        if builtin.contains(cmd_name) {
-            let output = run_builtin(cmd_name, &filtered_args, &builtin, rl);
-            if let Some(wfd) = write_fd {
-                write_outputs_to_fd(output, wfd);
-                close(wfd).ok();
-            } else {
-                for out in output {
-                    if let Some(msg) = out {
-                        println!("{}", msg.message);
-                    }
-                }
+    let levels = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(levels) if levels >= 1 => levels as usize,
+            Ok(_) => {
+                return vec![Some(err(format!(
+                    "{}: {}: loop count out of range",
+                    cmd, arg
+                )))]
             }
-        } else {
-            let mut cmd = Command::new(cmd_name);
-
-            if !filtered_args.is_empty() {
-                cmd.args(&filtered_args);
+            Err(_) => {
+                return vec![Some(err(format!(
+                    "{}: {}: numeric argument required",
+                    cmd, arg
+                )))]
             }
+        },
+    };
+
+    let levels = levels.min(shell.loop_depth);
+    shell.flow = if cmd == "break" {
+        Flow::Break(levels)
+    } else {
+        Flow::Continue(levels)
+    };
+    Vec::new()
+}
+
+fn cmd_return(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    if shell.frames.is_empty() && shell.sourcing == 0 {
+        return vec![Some(err(
+            "return: can only `return' from a function or sourced script".to_string(),
+        ))];
+    }
 
-            if let Some(fd) = prev_read {
-                let stdin = unsafe { Stdio::from_raw_fd(fd) };
-                cmd.stdin(stdin);
+    let code = match args.first() {
+        None => shell.last_status,
+        Some(arg) => match arg.parse::<i64>() {
+            Ok(code) => (code & 0xff) as i32,
+            Err(_) => {
+                shell.flow = Flow::Return;
+                return vec![
+                    Some(err(format!("return: {}: numeric argument required", arg))),
+                    Some(status(2)),
+                ];
             }
+        },
+    };
 
-            if let Some(wfd) = write_fd {
-                let stdout = unsafe { Stdio::from_raw_fd(wfd) };
-                cmd.stdout(stdout);
+    shell.flow = Flow::Return;
+    vec![Some(status(code))]
+}
+
+fn cmd_shift(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let count = match args.first() {
+        None => 1,
+        Some(arg) => match arg.parse::<usize>() {
+            Ok(count) => count,
+            Err(_) => {
+                return vec![Some(err(format!(
+                    "shift: {}: numeric argument required",
+                    arg
+                )))]
             }
+        },
+    };
+
+    if count > shell.positional.len() {
+        return vec![Some(status(1))];
+    }
+    shell.positional.drain(..count);
+    Vec::new()
+}
+
+fn cmd_local(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    if shell.frames.is_empty() {
+        return vec![Some(err(
+            "local: can only be used in a function".to_string()
+        ))];
+    }
 
-            let child = cmd.spawn().expect(&format!("Failed to run {}", cmd_name));
-            children.push(child);
+    let mut outputs = Vec::new();
+    for arg in args {
+        let (name, value) = match arg.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (arg.as_str(), None),
+        };
+        if !lexer::is_name(name) {
+            outputs.push(Some(err(format!(
+                "local: `{}': not a valid identifier",
+                arg
+            ))));
+            continue;
         }
 
-        if let Some(wfd) = write_fd {
-            close(wfd).ok();
+        shell.make_local(name);
+        match value {
+            Some(value) => shell.set_var(name, value.to_string()),
+            None => shell.unset_var(name),
         }
+    }
+
+    outputs
+}
 
-        if let Some(rfd) = prev_read {
-            close(rfd).ok();
+// `source` and `.`, with `args` as the positional parameters of the file if
+// there are any.
+fn cmd_source(shell: &mut Shell, cmd: &str, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let (name, args) = match args.split_first() {
+        Some(split) => split,
+        None => {
+            return vec![
+                Some(err(format!("{}: filename argument required", cmd))),
+                Some(status(2)),
+            ]
         }
+    };
 
-        prev_read = read_fd;
+    // A name without a slash is looked for in PATH, then in the current
+    // directory.
+    let path = match shell.get_var("PATH") {
+        Some(path_var) if !name.contains('/') => env::split_paths(path_var)
+            .map(|dir| dir.join(name))
+            .find(|full_path| full_path.is_file())
+            .unwrap_or_else(|| PathBuf::from(name)),
+        _ => PathBuf::from(name),
+    };
+    let positional = if args.is_empty() {
+        None
+    } else {
+        Some(std::mem::replace(&mut shell.positional, args.to_vec()))
+    };
+    let result = source_file(shell, &path);
+    if let Some(positional) = positional {
+        shell.positional = positional;
     }
 
-    for mut child in children {
-        child.wait().expect("Failed to wait for child");
+    match result {
+        Ok(code) => vec![Some(status(code))],
+        Err(e) => vec![Some(err(format!(
+            "{}: {}: {}",
+            cmd,
+            name,
+            redirect::describe_io_error(&e)
+        )))],
     }
 }
 
-pub fn command_handler(
-    cmd: &str,
-    args: &Vec<String>,
-    builtin: &Vec<String>,
-    output_conf: OutputConf,
-    rl: &mut Editor<ShellCompleter, DefaultHistory>,
-) {
+// Runs the commands of a file in the current shell, until its end or a
+// `return`.
+pub fn source_file(shell: &mut Shell, path: &Path) -> io::Result<i32> {
+    let text = std::fs::read_to_string(path)?;
+    shell.sourcing += 1;
+
+    let mut source = Source::Text(text, 0);
+    let mut code = 0;
+    while shell.flow == Flow::Normal {
+        match input::input(shell, &mut source) {
+            Some(Ok(list)) => code = run_list(&list, shell),
+            Some(Err(e)) => {
+                eprintln!("{}: {}", path.display(), e);
+                code = 2;
+                break;
+            }
+            None => break,
+        }
+    }
+    if shell.flow == Flow::Return {
+        code = shell.last_status;
+        shell.flow = Flow::Normal;
+    }
+    run_trap(shell, Trap::Return);
+
+    shell.sourcing -= 1;
+    Ok(code)
+}
+
+fn cmd_jobs(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
     let mut outputs = Vec::new();
+    let mut long = false;
+    let mut pids = false;
+    let mut specs = Vec::new();
 
-    if builtin.contains(&cmd.to_string()) {
-        outputs = run_builtin(cmd, args, builtin, rl);
-    } else {
-        let output = cmd_run(cmd, args);
-        for value in output {
-            outputs.push(value);
+    for arg in args {
+        match arg.as_str() {
+            "-l" => long = true,
+            "-p" => pids = true,
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return vec![
+                    Some(err(format!("jobs: {}: invalid option", arg))),
+                    Some(err("jobs: usage: jobs [-lp] [jobspec ...]".to_string())),
+                    Some(status(2)),
+                ];
+            }
+            _ => specs.push(arg.as_str()),
+        }
+    }
+
+    // A forked copy only lists the jobs it got from its parent.
+    if let Some(parent_jobs) = &shell.parent_jobs {
+        list_jobs(parent_jobs, &specs, long, pids, &mut outputs);
+        return outputs;
+    }
+
+    jobs::update(shell);
+    let indices = list_jobs(&shell.jobs, &specs, long, pids, &mut outputs);
+    for &index in &indices {
+        shell.jobs[index].notified = true;
+    }
+
+    // Finished jobs are forgotten once they have been reported.
+    let mut index = 0;
+    shell.jobs.retain(|job| {
+        index += 1;
+        !(indices.contains(&(index - 1)) && job.finished())
+    });
+    outputs
+}
+
+// Lists the jobs named by specs, or all of them. Returns their indices.
+fn list_jobs(
+    jobs: &[Job],
+    specs: &[&str],
+    long: bool,
+    pids: bool,
+    outputs: &mut Vec<Option<OutputMsg>>,
+) -> Vec<usize> {
+    let mut indices = Vec::new();
+    if specs.is_empty() {
+        indices.extend(0..jobs.len());
+    }
+    for &spec in specs {
+        match jobs::find(jobs, Some(spec)) {
+            Ok(index) => indices.push(index),
+            Err(message) => outputs.push(Some(err(format!("jobs: {}", message)))),
+        }
+    }
+
+    for &index in &indices {
+        let line = if pids {
+            let pid = jobs[index].pid();
+            pid.map(|pid| pid.to_string()).unwrap_or_default()
+        } else {
+            jobs::describe(jobs, index, long)
+        };
+        outputs.push(Some(msg(line)));
+    }
+    indices
+}
+
+fn cmd_fg(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    if !shell.job_control {
+        return vec![Some(err("fg: no job control".to_string()))];
+    }
+
+    jobs::update(shell);
+    let index = match jobs::find(&shell.jobs, args.first().map(String::as_str)) {
+        Ok(index) => index,
+        Err(message) => return vec![Some(err(format!("fg: {}", message)))],
+    };
+    if shell.jobs[index].finished() {
+        shell.jobs.remove(index);
+        return vec![Some(err("fg: job has terminated".to_string()))];
+    }
+
+    println!("{}", shell.jobs[index].text);
+    io::stdout().flush().ok();
+    vec![Some(status(jobs::foreground(shell, index)))]
+}
+
+fn cmd_bg(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    if !shell.job_control {
+        return vec![Some(err("bg: no job control".to_string()))];
+    }
+
+    jobs::update(shell);
+    let mut outputs = Vec::new();
+    let specs: Vec<Option<&str>> = match args {
+        [] => vec![None],
+        _ => args.iter().map(|arg| Some(arg.as_str())).collect(),
+    };
+
+    for spec in specs {
+        let index = match jobs::find(&shell.jobs, spec) {
+            Ok(index) => index,
+            Err(message) => {
+                outputs.push(Some(err(format!("bg: {}", message))));
+                continue;
+            }
+        };
+        let id = shell.jobs[index].id;
+        match shell.jobs[index].state() {
+            State::Stopped(_) => {
+                jobs::background(shell, index);
+                let marker = jobs::marker(&shell.jobs, index);
+                let line = format!("[{}]{} {} &", id, marker, shell.jobs[index].text);
+                outputs.push(Some(msg(line)));
+            }
+            State::Running => {
+                outputs.push(Some(err(format!("bg: job {} already in background", id))));
+            }
+            _ => outputs.push(Some(err("bg: job has terminated".to_string()))),
+        }
+    }
+
+    outputs
+}
+
+fn cmd_wait(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    jobs::update(shell);
+
+    if args.is_empty() {
+        let mut index = 0;
+        while index < shell.jobs.len() {
+            match shell.jobs[index].state() {
+                State::Stopped(_) => index += 1,
+                _ => {
+                    jobs::wait_job(shell, index);
+                }
+            }
+        }
+        return Vec::new();
+    }
+
+    let mut outputs = Vec::new();
+    let mut code = 0;
+    for arg in args {
+        let found = if arg.starts_with('%') {
+            jobs::find(&shell.jobs, Some(arg)).map_err(|message| format!("wait: {}", message))
+        } else {
+            match arg.parse::<i32>() {
+                Ok(pid) => shell
+                    .jobs
+                    .iter()
+                    .position(|job| {
+                        job.processes
+                            .iter()
+                            .any(|process| process.pid == Some(Pid::from_raw(pid)))
+                    })
+                    .ok_or(format!("wait: pid {} is not a child of this shell", pid)),
+                Err(_) => {
+                    outputs.push(Some(err(format!(
+                        "wait: `{}': not a pid or valid job spec",
+                        arg
+                    ))));
+                    code = 2;
+                    continue;
+                }
+            }
+        };
+
+        code = match found {
+            Ok(index) => jobs::wait_job(shell, index),
+            Err(message) => {
+                outputs.push(Some(err(message)));
+                127
+            }
+        };
+    }
+
+    outputs.push(Some(status(code)));
+    outputs
+}
+
+fn cmd_disown(shell: &mut Shell, args: &[String]) -> Vec<Option<OutputMsg>> {
+    let mut all = false;
+    let mut running = false;
+    let mut keep = false;
+    let mut specs = Vec::new();
+
+    for arg in args {
+        match arg.as_str() {
+            "-a" => all = true,
+            "-r" => running = true,
+            // Jobs are not sent SIGHUP when the shell exits, so `-h` only
+            // checks the job specs.
+            "-h" => keep = true,
+            _ if arg.starts_with('-') && arg.len() > 1 => {
+                return vec![
+                    Some(err(format!("disown: {}: invalid option", arg))),
+                    Some(err(
+                        "disown: usage: disown [-h] [-ar] [jobspec ... | pid ...]".to_string(),
+                    )),
+                    Some(status(2)),
+                ];
+            }
+            _ => specs.push(arg.as_str()),
+        }
+    }
+
+    jobs::update(shell);
+    if all && specs.is_empty() {
+        if !keep {
+            shell
+                .jobs
+                .retain(|job| running && job.state() != State::Running);
+        }
+        return Vec::new();
+    }
+
+    let mut outputs = Vec::new();
+    let specs: Vec<Option<&str>> = match specs.as_slice() {
+        [] => vec![None],
+        _ => specs.into_iter().map(Some).collect(),
+    };
+    for spec in specs {
+        match jobs::find(&shell.jobs, spec) {
+            Ok(_) if keep => {}
+            Ok(index) => {
+                shell.jobs.remove(index);
+            }
+            Err(message) => outputs.push(Some(err(format!("disown: {}", message)))),
+        }
+    }
+    outputs
+}
+
+fn cmd_pwd() -> Option<OutputMsg> {
+    Some(msg(format!("{}", env::current_dir().unwrap().display())))
+}
+
+fn cmd_cd(shell: &mut Shell, args: &[String]) -> Option<OutputMsg> {
+    if args.len() > 1 {
+        return Some(err("cd: too many arguments".to_string()));
+    }
+
+    let (path, print) = match args.first().map(String::as_str) {
+        None => match shell.get_var("HOME") {
+            Some(home) => (home.to_string(), false),
+            None => return Some(err("cd: HOME not set".to_string())),
+        },
+        Some("-") => match shell.get_var("OLDPWD") {
+            Some(old) => (old.to_string(), true),
+            None => return Some(err("cd: OLDPWD not set".to_string())),
+        },
+        Some(path) => (path.to_string(), false),
+    };
+
+    let old = env::current_dir().ok();
+    if let Err(e) = env::set_current_dir(&path) {
+        return Some(err(format!(
+            "cd: {}: {}",
+            path,
+            redirect::describe_io_error(&e)
+        )));
+    }
+
+    if let Some(old) = old {
+        shell.set_var("OLDPWD", old.display().to_string());
+    }
+    let new = env::current_dir()
+        .map(|dir| dir.display().to_string())
+        .unwrap_or(path);
+    shell.set_var("PWD", new.clone());
+
+    if print {
+        Some(msg(new))
+    } else {
+        None
+    }
+}
+use std::fmt::Write as FmtWrite;
+
+fn delete_header(filename: &str) -> std::io::Result<()> {
+    let file = File::open(filename)?;
+    let reader = BufReader::new(file);
+
+    let lines: Vec<String> = reader
+        .lines()
+        .map_while(Result::ok)
+        .filter(|line| line.trim() != "#V2")
+        .collect();
+
+    let mut file = OpenOptions::new()
+        .write(true)
+        .truncate(true)
+        .open(filename)?;
+
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+
+    Ok(())
+}
+
+fn cmd_history(
+    rl: &mut Editor<ShellCompleter, DefaultHistory>,
+    args: &[String],
+) -> Option<OutputMsg> {
+    let mut history = String::new();
+
+    let history_len = rl.history().iter().count();
+
+    let mut n: usize = history_len;
+    if !args.is_empty() {
+        if args[0] == "-r" {
+            rl.load_history(&args[1]).ok();
+            return None;
+        } else if args[0] == "-w" || args[0] == "-a" {
+            let written = match args[0].as_str() {
+                "-w" => rl.save_history(&args[1]),
+                _ => rl.append_history(&args[1]),
+            };
+            // Appending nothing leaves no file behind to clean up.
+            let result =
+                written
+                    .map_err(|e| e.to_string())
+                    .and_then(|_| match delete_header(&args[1]) {
+                        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                            Err(redirect::describe_io_error(&e))
+                        }
+                        _ => Ok(()),
+                    });
+            return match result {
+                Ok(()) => None,
+                Err(message) => Some(err(format!("history: {}: {}", args[1], message))),
+            };
+        } else {
+            n = args[0].parse().expect("Not a valid number");
+        }
+    }
+
+    let start = history_len.saturating_sub(n);
+
+    for (i, entry) in rl.history().iter().skip(start).enumerate() {
+        writeln!(&mut history, "\t{} {}", start + i + 1, entry).unwrap();
+    }
+    Some(msg(history.trim_end().to_string()))
+}
+
+fn spawn_error(cmd: &str, error: &io::Error) -> (OutputMsg, i32) {
+    match error.kind() {
+        io::ErrorKind::PermissionDenied => (err(format!("{}: Permission denied", cmd)), 126),
+        _ => (err(format!("{}: command not found", cmd)), 127),
+    }
+}
+
+// A command that has been started: either a process, be it a program or a
+// forked copy of the shell, or one that ran inside the shell.
+enum Launched {
+    Process(Pid),
+    Done(i32),
+}
+
+// Under job control, a child joins the process group of its job. It gets
+// back the signals the shell ignores for itself.
+fn configure_child(command: &mut Command, group: Option<Group>, ignored: &[Signal]) {
+    if group.is_none() && ignored.is_empty() {
+        return;
+    }
+    let ignored = ignored.to_vec();
+    unsafe {
+        command.pre_exec(move || {
+            jobs::setup_child(group);
+            signals::restore(&ignored);
+            Ok(())
+        });
+    }
+}
+
+fn cmd_run(
+    cmd: &str,
+    args: &[String],
+    env: &[(String, String)],
+    streams: &Streams,
+    group: Option<Group>,
+    ignored: &[Signal],
+) -> Launched {
+    let mut command = Command::new(cmd);
+    command.args(args).env_clear().envs(env.iter().cloned());
+    streams.configure(&mut command);
+    configure_child(&mut command, group, ignored);
+
+    let mut spawned = command.spawn();
+    // A file without a `#!` line that the system can't execute is taken to
+    // be a script for this shell.
+    if let Err(e) = &spawned {
+        if e.raw_os_error() == Some(Errno::ENOEXEC as i32) {
+            spawned = run_script(cmd, args, env, streams, group, ignored);
+        }
+    }
+
+    match spawned {
+        Ok(child) => Launched::Process(Pid::from_raw(child.id() as i32)),
+        Err(e) => {
+            let (message, status) = spawn_error(cmd, &e);
+            output_handler(cmd, vec![Some(message)], streams);
+            Launched::Done(status)
+        }
+    }
+}
+
+fn run_script(
+    cmd: &str,
+    args: &[String],
+    env: &[(String, String)],
+    streams: &Streams,
+    group: Option<Group>,
+    ignored: &[Signal],
+) -> io::Result<Child> {
+    let script = if cmd.contains('/') {
+        PathBuf::from(cmd)
+    } else {
+        let path_var = env
+            .iter()
+            .find(|(name, _)| name == "PATH")
+            .map_or("", |(_, value)| value.as_str());
+        find_in_path(cmd, path_var).unwrap_or_else(|| PathBuf::from(cmd))
+    };
+
+    let mut command = Command::new(env::current_exe()?);
+    command
+        .arg(script)
+        .args(args)
+        .env_clear()
+        .envs(env.iter().cloned());
+    streams.configure(&mut command);
+    configure_child(&mut command, group, ignored);
+    command.spawn()
+}
+
+fn builtin_status(outputs: &[Option<OutputMsg>]) -> i32 {
+    let explicit = outputs
+        .iter()
+        .flatten()
+        .rev()
+        .find_map(|output| match output.msg_type {
+            OutputMsgType::Status(code) => Some(code),
+            _ => None,
+        });
+    if let Some(code) = explicit {
+        return code;
+    }
+
+    let failed = outputs
+        .iter()
+        .flatten()
+        .any(|output| matches!(output.msg_type, OutputMsgType::StdErr));
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+// Writes what a builtin printed, and returns its status. A builtin that
+// couldn't write its output fails, as in `echo x > /dev/full`.
+fn output_handler(name: &str, outputs: Vec<Option<OutputMsg>>, streams: &Streams) -> i32 {
+    let mut status = builtin_status(&outputs);
+    let mut stdout = streams.writer(1);
+    let mut stderr = streams.writer(2);
+
+    for value in outputs.into_iter().flatten() {
+        match value.msg_type {
+            OutputMsgType::StdOut => {
+                let written = writeln!(stdout, "{}", value.message).and_then(|_| stdout.flush());
+                if let Err(e) = written {
+                    let message = redirect::describe_io_error(&e);
+                    writeln!(stderr, "{}: write error: {}", name, message).ok();
+                    stdout = Box::new(io::sink());
+                    status = 1;
+                }
+            }
+            OutputMsgType::StdErr => {
+                writeln!(stderr, "{}", value.message).ok();
+            }
+            OutputMsgType::Status(_) => {}
+        }
+    }
+    status
+}
+
+fn cmd_exit(shell: &mut Shell, args: &[String]) -> ! {
+    let status = match args.first() {
+        Some(arg) => match arg.parse::<i32>() {
+            Ok(n) => n & 0xff,
+            Err(_) => {
+                eprintln!("exit: {}: numeric argument required", arg);
+                2
+            }
+        },
+        None => shell.last_status,
+    };
+    exit_shell(shell, status)
+}
+
+// Leaves the shell, after the EXIT trap, saving the history of an
+// interactive session.
+pub fn exit_shell(shell: &mut Shell, status: i32) -> ! {
+    // The trap is taken out first so that an `exit` inside it ends the shell.
+    if let Some(action) = shell.traps.remove(&Trap::Exit) {
+        shell.last_status = status;
+        run_action(shell, &action);
+    }
+
+    let histfile = shell.get_var("HISTFILE").map(str::to_string);
+
+    if let Some(path) = histfile.filter(|_| shell.interactive && !shell.subshell) {
+        shell.rl.save_history(&path).ok();
+        delete_header(&path).ok();
+    }
+    io::stdout().flush().ok();
+    std::process::exit(status);
+}
+
+fn run_builtin(cmd: &str, args: &[String], shell: &mut Shell) -> Vec<Option<OutputMsg>> {
+    let mut outputs = Vec::new();
+    match cmd {
+        "exit" => cmd_exit(shell, args),
+        "echo" => {
+            outputs.push(cmd_echo(args));
+        }
+        "type" => {
+            for arg in args {
+                outputs.push(cmd_type(arg, shell));
+            }
+        }
+        "pwd" => {
+            outputs.push(cmd_pwd());
+        }
+        "cd" => {
+            outputs.push(cmd_cd(shell, args));
+        }
+        "history" => {
+            outputs.push(cmd_history(&mut shell.rl, args));
+        }
+        "export" => {
+            outputs.extend(cmd_export(shell, args));
+        }
+        "unset" => {
+            outputs.extend(cmd_unset(shell, args));
+        }
+        "env" => {
+            outputs.extend(cmd_env(shell));
+        }
+        "shopt" => {
+            outputs.extend(cmd_shopt(shell, args));
+        }
+        "let" => {
+            outputs.extend(cmd_let(shell, args));
+        }
+        "alias" => {
+            outputs.extend(cmd_alias(shell, args));
+        }
+        "jobs" => {
+            outputs.extend(cmd_jobs(shell, args));
+        }
+        "fg" => {
+            outputs.extend(cmd_fg(shell, args));
+        }
+        "bg" => {
+            outputs.extend(cmd_bg(shell, args));
+        }
+        "wait" => {
+            outputs.extend(cmd_wait(shell, args));
+        }
+        "disown" => {
+            outputs.extend(cmd_disown(shell, args));
+        }
+        "unalias" => {
+            outputs.extend(cmd_unalias(shell, args));
+        }
+        "trap" => {
+            outputs.extend(cmd_trap(shell, args));
+        }
+        "set" => {
+            outputs.extend(cmd_set(shell, args));
+        }
+        "break" | "continue" => {
+            outputs.extend(cmd_loop_control(shell, cmd, args));
+        }
+        "return" => {
+            outputs.extend(cmd_return(shell, args));
+        }
+        "shift" => {
+            outputs.extend(cmd_shift(shell, args));
+        }
+        "local" => {
+            outputs.extend(cmd_local(shell, args));
+        }
+        _ => {}
+    }
+
+    outputs
+}
+
+// Applies the assignments in front of a command for the duration of that
+// command only. Returns the previous state of each variable.
+fn assign_temporarily(
+    shell: &mut Shell,
+    assignments: &[Assignment],
+) -> Result<Vec<(String, Option<Var>)>, String> {
+    let mut saved = Vec::new();
+
+    for assignment in assignments {
+        let value = expand::expand_assignment(shell, &assignment.value)?;
+        saved.push((
+            assignment.name.clone(),
+            shell.vars.get(&assignment.name).cloned(),
+        ));
+        let var = Var {
+            value: Some(value),
+            exported: true,
+        };
+        shell.vars.insert(assignment.name.clone(), var);
+    }
+
+    Ok(saved)
+}
+
+fn restore_vars(shell: &mut Shell, saved: Vec<(String, Option<Var>)>) {
+    for (name, var) in saved.into_iter().rev() {
+        match var {
+            Some(var) => {
+                shell.vars.insert(name, var);
+            }
+            None => shell.unset_var(&name),
+        }
+    }
+}
+
+// Quotes a word for `-x` output when it would not read back as itself.
+fn trace_quote(word: &str) -> String {
+    let plain = |c: char| c.is_ascii_alphanumeric() || "%+,-./:=@_^~".contains(c);
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    }
+    format!("'{}'", word.replace('\'', "'\\''"))
+}
+
+// The expanded value of PS4, which starts each line of `-x` output.
+fn trace_prefix(shell: &mut Shell) -> String {
+    let ps4 = match shell.get_var("PS4") {
+        Some(ps4) => ps4.to_string(),
+        None => return "+ ".to_string(),
+    };
+    let xtrace = std::mem::replace(&mut shell.options.xtrace, false);
+    let prefix = lexer::prompt(&ps4)
+        .map_err(|e| e.to_string())
+        .and_then(|word| expand::expand_word(shell, &word))
+        .unwrap_or(ps4);
+    shell.options.xtrace = xtrace;
+    prefix
+}
+
+// Prints a command about to run under `-x`, once the assignments in front
+// of it have been made.
+fn trace(shell: &mut Shell, assignments: &[Assignment], words: &[String]) {
+    let mut fields = Vec::new();
+    for assignment in assignments {
+        let value = shell.get_var(&assignment.name).unwrap_or("");
+        fields.push(format!("{}={}", assignment.name, trace_quote(value)));
+    }
+    fields.extend(words.iter().map(|word| trace_quote(word)));
+    eprintln!("{}{}", trace_prefix(shell), fields.join(" "));
+}
+
+// Runs commands inside the shell with the given streams installed on its own
+// descriptors, or in a forked copy when they are part of a pipeline.
+fn run_in_shell(
+    shell: &mut Shell,
+    streams: &Streams,
+    piped: bool,
+    run: impl FnOnce(&mut Shell) -> i32,
+) -> Launched {
+    if piped {
+        return fork_shell(shell, streams, run);
+    }
+
+    let saved = streams.install();
+    let status = run(shell);
+    saved.restore();
+    Launched::Done(status)
+}
+
+// Runs the action of a trap, if one is set, leaving `$?` as it was. No trap
+// runs while another one does. Returns whether the action ran.
+fn run_trap(shell: &mut Shell, trap: Trap) -> bool {
+    if shell.running_trap {
+        return false;
+    }
+    let action = match shell.traps.get(&trap) {
+        Some(action) if !action.is_empty() => action.clone(),
+        _ => return false,
+    };
+
+    let last_status = shell.last_status;
+    let pipestatus = shell.pipestatus.clone();
+    run_action(shell, &action);
+    shell.last_status = last_status;
+    shell.pipestatus = pipestatus;
+    true
+}
+
+fn run_action(shell: &mut Shell, action: &str) {
+    match shell.parse(action) {
+        Ok(list) => {
+            let running_trap = std::mem::replace(&mut shell.running_trap, true);
+            run_list(&list, shell);
+            shell.running_trap = running_trap;
+        }
+        Err(e) => eprintln!("trap: {}", e),
+    }
+}
+
+// Runs the traps of the signals that came in since the last command.
+fn run_pending_traps(shell: &mut Shell) {
+    if shell.running_trap {
+        return;
+    }
+    for signal in signals::take_pending() {
+        run_trap(shell, Trap::Signal(signal));
+    }
+}
+
+// Runs a function body with `args` as the positional parameters.
+fn call_function(body: &AstCommand, args: &[String], shell: &mut Shell) -> i32 {
+    let positional = std::mem::replace(&mut shell.positional, args.to_vec());
+    let loop_depth = std::mem::replace(&mut shell.loop_depth, 0);
+    shell.frames.push(HashMap::new());
+    // A function doesn't inherit these traps; one it sets itself stays set.
+    let inherited: Vec<_> = [Trap::Debug, Trap::Err, Trap::Return]
+        .into_iter()
+        .filter_map(|trap| Some((trap, shell.traps.remove(&trap)?)))
+        .collect();
+
+    let status = command_handler(body, shell);
+    if shell.flow == Flow::Return {
+        shell.flow = Flow::Normal;
+    }
+    run_trap(shell, Trap::Return);
+    for (trap, action) in inherited {
+        shell.traps.entry(trap).or_insert(action);
+    }
+
+    if let Some(frame) = shell.frames.pop() {
+        restore_vars(shell, frame.into_iter().collect());
+    }
+    shell.positional = positional;
+    shell.loop_depth = loop_depth;
+    status
+}
+
+// Starts a simple command with the given streams, applying its own
+// redirections on top. Builtins and functions run to completion before this
+// returns, unless they are part of a pipeline.
+fn launch(
+    simple: &SimpleCommand,
+    shell: &mut Shell,
+    mut streams: Streams,
+    piped: bool,
+) -> Launched {
+    shell.substitution_status = None;
+    let words = expand::expand_words(shell, &simple.words);
+    let applied = words.and_then(|words| {
+        let ops = input::redirection(shell, &simple.redirects)?;
+        streams.apply(&ops)?;
+        Ok(words)
+    });
+
+    let words = match applied {
+        Ok(words) => words,
+        Err(message) => {
+            eprintln!("{}", message);
+            return Launched::Done(1);
+        }
+    };
+
+    match words.split_first() {
+        Some((cmd, args)) => {
+            let saved = match assign_temporarily(shell, &simple.assignments) {
+                Ok(saved) => saved,
+                Err(message) => {
+                    eprintln!("{}", message);
+                    return Launched::Done(1);
+                }
+            };
+            if shell.options.xtrace {
+                trace(shell, &simple.assignments, &words);
+            }
+
+            // `env` only lists the environment itself; given a command to
+            // run, it is left to the system's `env`.
+            let builtin = shell.builtin.contains(cmd) && (cmd != "env" || args.is_empty());
+
+            let launched = if let Some(body) = shell.functions.get(cmd).cloned() {
+                run_in_shell(shell, &streams, piped, |shell| {
+                    call_function(&body, args, shell)
+                })
+            } else if cmd == "source" || cmd == "." {
+                run_in_shell(shell, &streams, piped, |shell| {
+                    let outputs = cmd_source(shell, cmd, args);
+                    output_handler(cmd, outputs, &Streams::default())
+                })
+            } else if builtin && piped {
+                // In a pipeline a builtin runs alongside the other stages,
+                // in a copy of the shell that its changes don't outlive.
+                run_in_shell(shell, &streams, piped, |shell| {
+                    let outputs = run_builtin(cmd, args, shell);
+                    output_handler(cmd, outputs, &Streams::default())
+                })
+            } else if builtin {
+                let outputs = run_builtin(cmd, args, shell);
+                Launched::Done(output_handler(cmd, outputs, &streams))
+            } else {
+                let launched = cmd_run(
+                    cmd,
+                    args,
+                    &shell.environment(),
+                    &streams,
+                    shell.group,
+                    &signals::for_children(shell),
+                );
+                if let Launched::Process(pid) = launched {
+                    jobs::started(shell, pid);
+                }
+                launched
+            };
+
+            restore_vars(shell, saved);
+            launched
+        }
+        None => run_in_shell(shell, &streams, piped, |shell| {
+            assign(&simple.assignments, shell)
+        }),
+    }
+}
+
+// Makes the assignments of a command that has no words.
+fn assign(assignments: &[Assignment], shell: &mut Shell) -> i32 {
+    for assignment in assignments {
+        match expand::expand_assignment(shell, &assignment.value) {
+            Ok(value) => {
+                if shell.options.xtrace {
+                    let prefix = trace_prefix(shell);
+                    eprintln!("{}{}={}", prefix, assignment.name, trace_quote(&value));
+                }
+                shell.set_var(&assignment.name, value)
+            }
+            Err(message) => {
+                eprintln!("{}", message);
+                return 1;
+            }
+        }
+    }
+    shell.substitution_status.unwrap_or(0)
+}
+
+fn wait_for(pid: Pid) -> i32 {
+    loop {
+        match waitpid(pid, None) {
+            Ok(WaitStatus::Exited(_, code)) => return code,
+            Ok(WaitStatus::Signaled(_, signal, _)) => return 128 + signal as i32,
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(_) => return 1,
+        }
+    }
+}
+
+fn wait(launched: Launched) -> i32 {
+    match launched {
+        Launched::Process(pid) => wait_for(pid),
+        Launched::Done(status) => status,
+    }
+}
+
+// Runs `run` in a forked copy of the shell with the given streams as its
+// own descriptors.
+fn fork_shell(
+    shell: &mut Shell,
+    streams: &Streams,
+    run: impl FnOnce(&mut Shell) -> i32,
+) -> Launched {
+    io::stdout().flush().ok();
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            if shell.job_control {
+                jobs::setup_child(shell.group);
+                shell.job_control = false;
+                shell.group = None;
+            }
+            signals::enter_subshell(shell);
+            // The jobs are the parent's to wait on and control.
+            shell.parent_jobs = Some(std::mem::take(&mut shell.jobs));
+            streams.install();
+            redirect::close_private();
+            shell.subshell = true;
+            shell.interactive = false;
+            let status = run(shell);
+            exit_shell(shell, status)
+        }
+        Ok(ForkResult::Parent { child }) => {
+            jobs::started(shell, child);
+            Launched::Process(child)
+        }
+        Err(e) => {
+            eprintln!("fork: {}", e.desc());
+            Launched::Done(1)
+        }
+    }
+}
+
+// Runs a command in a forked copy of the shell and returns what it wrote to
+// standard output, less any trailing newlines.
+pub fn substitute(command: &str, shell: &mut Shell) -> Result<String, String> {
+    let list = shell.parse(command).map_err(|e| e.to_string())?;
+    let (mut read, write) = redirect::pipe().map_err(|e| redirect::describe_io_error(&e))?;
+
+    let mut streams = Streams::default();
+    streams.set(1, write);
+    // The command runs as part of the job it is expanded for, in the
+    // shell's own process group.
+    let group = shell.group.take();
+    let launched = fork_shell(shell, &streams, |shell| run_list(&list, shell));
+    shell.group = group;
+    drop(streams);
+
+    let mut output = Vec::new();
+    read.read_to_end(&mut output).ok();
+
+    let status = wait(launched);
+    shell.last_status = status;
+    shell.substitution_status = Some(status);
+
+    let mut output = String::from_utf8_lossy(&output).into_owned();
+    while output.ends_with('\n') {
+        output.pop();
+    }
+    Ok(output)
+}
+
+// Evaluates a `((...))` command, which succeeds when the result is not 0.
+fn run_arith(word: &Word, shell: &mut Shell) -> i32 {
+    let result = expand::expand_string(shell, &word.parts)
+        .and_then(|expression| arith::eval(shell, &expression));
+    match result {
+        Ok(value) => (value == 0) as i32,
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        }
+    }
+}
+
+// Settles a pending `break` or `continue` after one pass of a loop body.
+// Returns whether the loop has to stop.
+fn loop_done(shell: &mut Shell) -> bool {
+    match shell.flow {
+        Flow::Normal => false,
+        Flow::Return | Flow::Interrupt => true,
+        Flow::Break(levels) => {
+            shell.flow = if levels > 1 {
+                Flow::Break(levels - 1)
+            } else {
+                Flow::Normal
+            };
+            true
+        }
+        Flow::Continue(levels) if levels > 1 => {
+            shell.flow = Flow::Continue(levels - 1);
+            true
+        }
+        Flow::Continue(_) => {
+            shell.flow = Flow::Normal;
+            false
+        }
+    }
+}
+
+fn run_while(until: bool, condition: &List, body: &List, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+
+    loop {
+        let passed = run_condition(condition, shell) == 0;
+        if loop_done(shell) {
+            break;
+        }
+        if passed == until {
+            break;
+        }
+        status = run_list(body, shell);
+        if loop_done(shell) {
+            break;
+        }
+    }
+
+    status
+}
+
+fn run_for(name: &str, words: &Option<Vec<Word>>, body: &List, shell: &mut Shell) -> i32 {
+    let values = match words {
+        Some(words) => match expand::expand_words(shell, words) {
+            Ok(values) => values,
+            Err(message) => {
+                eprintln!("{}", message);
+                return 1;
+            }
+        },
+        None => shell.positional.clone(),
+    };
+
+    let mut status = 0;
+    for value in values {
+        shell.set_var(name, value);
+        status = run_list(body, shell);
+        if loop_done(shell) {
+            break;
+        }
+    }
+
+    status
+}
+
+fn evaluate(shell: &mut Shell, expression: &str) -> Option<i64> {
+    match arith::eval(shell, expression) {
+        Ok(value) => Some(value),
+        Err(message) => {
+            eprintln!("{}", message);
+            None
+        }
+    }
+}
+
+// Splits the header of an arithmetic `for` at its top-level semicolons. The
+// clauses stay unexpanded, as each is expanded again whenever it is evaluated.
+fn arith_clauses(header: &Word) -> Option<[Word; 3]> {
+    let mut clauses = vec![Word::default()];
+    for part in &header.parts {
+        match part {
+            WordPart::Literal(text) => {
+                for (i, piece) in text.split(';').enumerate() {
+                    if i > 0 {
+                        clauses.push(Word::default());
+                    }
+                    if !piece.is_empty() {
+                        let clause = clauses.last_mut().unwrap();
+                        clause.parts.push(WordPart::Literal(piece.to_string()));
+                    }
+                }
+            }
+            part => clauses.last_mut().unwrap().parts.push(part.clone()),
+        }
+    }
+    clauses.try_into().ok()
+}
+
+fn evaluate_clause(shell: &mut Shell, clause: &Word) -> Option<i64> {
+    match expand::expand_string(shell, &clause.parts) {
+        Ok(expression) => evaluate(shell, &expression),
+        Err(message) => {
+            eprintln!("{}", message);
+            None
+        }
+    }
+}
+
+fn run_arith_for(header: &Word, body: &List, shell: &mut Shell) -> i32 {
+    let [init, condition, step] = match arith_clauses(header) {
+        Some(clauses) => clauses,
+        None => {
+            eprintln!(
+                "(({})): syntax error: expected three expressions",
+                header.text()
+            );
+            return 1;
+        }
+    };
+
+    let mut status = 0;
+    if evaluate_clause(shell, &init).is_none() {
+        return 1;
+    }
+    loop {
+        // An empty condition is always true.
+        let passed = condition.text().trim().is_empty() || {
+            match evaluate_clause(shell, &condition) {
+                Some(value) => value != 0,
+                None => return 1,
+            }
+        };
+        if !passed {
+            break;
+        }
+        status = run_list(body, shell);
+        if loop_done(shell) {
+            break;
+        }
+        if evaluate_clause(shell, &step).is_none() {
+            return 1;
+        }
+    }
+
+    status
+}
+
+fn run_case(word: &Word, items: &[CaseItem], shell: &mut Shell) -> i32 {
+    let result = expand::expand_word(shell, word).and_then(|value| {
+        for item in items {
+            for pattern in &item.patterns {
+                if expand::expand_pattern(shell, pattern)?.matches(&value) {
+                    return Ok(Some(&item.body));
+                }
+            }
+        }
+        Ok(None)
+    });
+
+    match result {
+        Ok(Some(body)) => run_list(body, shell),
+        Ok(None) => 0,
+        Err(message) => {
+            eprintln!("{}", message);
+            1
+        }
+    }
+}
+
+fn run_compound(compound: &CompoundCommand, shell: &mut Shell) -> i32 {
+    match compound {
+        CompoundCommand::Group(list) | CompoundCommand::Subshell(list) => run_list(list, shell),
+        CompoundCommand::If(branches, otherwise) => {
+            for (condition, body) in branches {
+                let passed = run_condition(condition, shell) == 0;
+                if shell.flow != Flow::Normal {
+                    return shell.last_status;
+                }
+                if passed {
+                    return run_list(body, shell);
+                }
+            }
+            match otherwise {
+                Some(body) => run_list(body, shell),
+                None => 0,
+            }
+        }
+        CompoundCommand::While(until, condition, body) => {
+            shell.loop_depth += 1;
+            let status = run_while(*until, condition, body, shell);
+            shell.loop_depth -= 1;
+            status
+        }
+        CompoundCommand::For(name, words, body) => {
+            shell.loop_depth += 1;
+            let status = run_for(name, words, body, shell);
+            shell.loop_depth -= 1;
+            status
+        }
+        CompoundCommand::ArithFor(header, body) => {
+            shell.loop_depth += 1;
+            let status = run_arith_for(header, body, shell);
+            shell.loop_depth -= 1;
+            status
+        }
+        CompoundCommand::Case(word, items) => run_case(word, items, shell),
+    }
+}
+
+// Starts a command. Compound commands run inside the shell with their
+// redirections installed on its own descriptors, unless they are part of a
+// pipeline or a subshell, which run in a forked copy of the shell.
+fn start(command: &AstCommand, shell: &mut Shell, mut streams: Streams, piped: bool) -> Launched {
+    match command {
+        AstCommand::Simple(simple) => launch(simple, shell, streams, piped),
+        AstCommand::Arith(word) => Launched::Done(run_arith(word, shell)),
+        AstCommand::Function(name, body) => {
+            shell.functions.insert(name.clone(), body.clone());
+            Launched::Done(0)
+        }
+        AstCommand::Compound(compound, redirects) => {
+            let applied = input::redirection(shell, redirects).and_then(|ops| streams.apply(&ops));
+            if let Err(message) = applied {
+                eprintln!("{}", message);
+                return Launched::Done(1);
+            }
+
+            if piped || matches!(compound, CompoundCommand::Subshell(_)) {
+                return fork_shell(shell, &streams, |shell| run_compound(compound, shell));
+            }
+
+            let saved = streams.install();
+            let status = run_compound(compound, shell);
+            saved.restore();
+            Launched::Done(status)
+        }
+    }
+}
+
+// Starts every stage of a pipeline, each reading from the one before.
+fn start_pipeline(pipeline: &Pipeline, shell: &mut Shell) -> Vec<Launched> {
+    let mut launched = Vec::new();
+    let mut prev_read: Option<File> = None;
+    let stages = pipeline.commands.len();
+
+    for (i, stage) in pipeline.commands.iter().enumerate() {
+        let mut streams = Streams::default();
+        if let Some(read) = prev_read.take() {
+            streams.set(0, read);
+        }
+
+        if i < stages - 1 {
+            let (read, write) = redirect::pipe().expect("pipe failed");
+            streams.set(1, write);
+            prev_read = Some(read);
+        }
+
+        launched.push(start(stage, shell, streams, true));
+    }
+
+    launched
+}
+
+// Waits for a foreground pipeline. If it is stopped it becomes a job.
+fn wait_pipeline(pipeline: &Pipeline, launched: Vec<Launched>, shell: &mut Shell) -> Vec<i32> {
+    let mut processes: Vec<Process> = launched
+        .into_iter()
+        .map(|launched| match launched {
+            Launched::Process(pid) => Process {
+                pid: Some(pid),
+                state: State::Running,
+            },
+            Launched::Done(status) => Process {
+                pid: None,
+                state: State::Exited(status),
+            },
+        })
+        .collect();
+    jobs::wait_foreground(shell, &mut processes);
+    let statuses = processes
+        .iter()
+        .map(|process| process.state.status())
+        .collect();
+
+    // An interactive shell reports how a job was killed, and leaves the rest
+    // of the command line when it was interrupted from the terminal, as if
+    // it had been interrupted itself.
+    let killed = processes.iter().find_map(|process| match process.state {
+        State::Killed(signal) => Some(signal),
+        _ => None,
+    });
+    match killed {
+        Some(signal) if shell.interactive && signal == Signal::SIGINT as i32 => {
+            eprintln!();
+            run_trap(shell, Trap::Signal(Signal::SIGINT));
+            shell.flow = Flow::Interrupt;
+        }
+        Some(signal) if shell.interactive && signal != Signal::SIGPIPE as i32 => {
+            eprintln!("{}", jobs::signal_name(signal));
+        }
+        _ => {}
+    }
+
+    let stopped = processes
+        .iter()
+        .any(|process| matches!(process.state, State::Stopped(_)));
+    if stopped {
+        let pgid = shell.group.and_then(|group| group.pgid);
+        jobs::add(shell, pgid, processes, pipeline.to_string());
+        let index = shell.jobs.len() - 1;
+        shell.jobs[index].notified = true;
+        eprintln!("\n{}", jobs::describe(&shell.jobs, index, false));
+    }
+    statuses
+}
+
+pub fn command_handler(command: &AstCommand, shell: &mut Shell) -> i32 {
+    wait(start(command, shell, Streams::default(), false))
+}
+
+// Runs a pipeline in the foreground. Under job control its processes get a
+// process group of their own, which is given the terminal.
+fn run_pipeline_or_command(pipeline: &Pipeline, shell: &mut Shell) -> i32 {
+    run_trap(shell, Trap::Debug);
+    // The status of a pipeline started with `!` is tested, so `-e` and the
+    // ERR trap don't apply inside it.
+    shell.conditional += pipeline.negated as usize;
+
+    let group = shell.group;
+    if shell.job_control {
+        shell.group = Some(Group {
+            pgid: None,
+            terminal: Some(shell.terminal),
+        });
+    }
+
+    let launched = match pipeline.commands.as_slice() {
+        [command] => vec![start(command, shell, Streams::default(), false)],
+        _ => start_pipeline(pipeline, shell),
+    };
+    let statuses = wait_pipeline(pipeline, launched, shell);
+
+    shell.group = group;
+    shell.set_statuses(statuses);
+    shell.conditional -= pipeline.negated as usize;
+    if pipeline.negated {
+        shell.last_status = (shell.last_status == 0) as i32;
+    }
+    shell.last_status
+}
+
+// Runs a list in a forked copy of the shell, as a background job.
+fn run_background(and_or: &AndOr, shell: &mut Shell) {
+    // Without job control a background job has no claim on the terminal,
+    // so it reads from /dev/null instead.
+    let mut streams = Streams::default();
+    if !shell.job_control {
+        if let Ok(null) = File::open("/dev/null") {
+            streams.set(0, null);
+        }
+    }
+
+    let group = shell.group;
+    shell.group = shell.job_control.then_some(Group {
+        pgid: None,
+        terminal: None,
+    });
+    let launched = fork_shell(shell, &streams, |shell| {
+        // Nor can it be interrupted from the terminal.
+        if !shell.job_control {
+            signals::set_ignored(&[Signal::SIGINT, Signal::SIGQUIT]);
+        }
+        run_and_or(and_or, shell).0
+    });
+    shell.group = group;
+
+    if let Launched::Process(pid) = launched {
+        shell.last_background = Some(pid);
+        let process = Process {
+            pid: Some(pid),
+            state: State::Running,
+        };
+        let pgid = shell.job_control.then_some(pid);
+        let id = jobs::add(shell, pgid, vec![process], and_or.to_string());
+        if shell.interactive {
+            eprintln!("[{}] {}", id, pid);
+        }
+    }
+}
+
+// Runs an `&&` and `||` chain. Returns its status and whether `-e` applies,
+// which it only does when the last pipeline of the chain ran.
+fn run_and_or(and_or: &AndOr, shell: &mut Shell) -> (i32, bool) {
+    let chained = !and_or.rest.is_empty();
+    shell.conditional += chained as usize;
+    let mut status = run_pipeline_or_command(&and_or.first, shell);
+    shell.conditional -= chained as usize;
+    let mut checked = !chained && !and_or.first.negated;
+
+    for (i, (connector, pipeline)) in and_or.rest.iter().enumerate() {
+        if shell.flow != Flow::Normal {
+            break;
+        }
+        let run = match connector {
+            Connector::And => status == 0,
+            Connector::Or => status != 0,
+        };
+        if run {
+            let last = i + 1 == and_or.rest.len();
+            shell.conditional += !last as usize;
+            status = run_pipeline_or_command(pipeline, shell);
+            shell.conditional -= !last as usize;
+            checked = last && !pipeline.negated;
+        }
+    }
+
+    (status, checked)
+}
+
+// Runs a list whose status is tested, where `-e` doesn't apply.
+fn run_condition(list: &List, shell: &mut Shell) -> i32 {
+    shell.conditional += 1;
+    let status = run_list(list, shell);
+    shell.conditional -= 1;
+    status
+}
+
+pub fn run_list(list: &List, shell: &mut Shell) -> i32 {
+    let mut status = 0;
+
+    for and_or in &list.items {
+        if and_or.background {
+            run_background(and_or, shell);
+            shell.set_statuses(vec![0]);
+            status = 0;
+            run_pending_traps(shell);
+            continue;
+        }
+
+        shell.err_trapped = false;
+        let (and_or_status, checked) = run_and_or(and_or, shell);
+        status = and_or_status;
+        run_pending_traps(shell);
+
+        if shell.flow != Flow::Normal {
+            break;
+        }
+        if checked && status != 0 && shell.conditional == 0 {
+            if !shell.err_trapped {
+                shell.err_trapped = run_trap(shell, Trap::Err);
+            }
+            if shell.options.errexit {
+                exit_shell(shell, status);
+            }
         }
     }
 
-    output_handler(outputs, output_conf);
+    status
 }
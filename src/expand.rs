@@ -0,0 +1,619 @@
+use crate::arith;
+use crate::ast::{Param, ParamOp, ReplaceMode, Word, WordPart};
+use crate::brace;
+use crate::commands;
+use crate::glob;
+use crate::lexer;
+use crate::pattern::{self, Pattern};
+use crate::shell::Shell;
+
+use nix::unistd::{Uid, User};
+
+const DEFAULT_IFS: &str = " \t\n";
+
+// A run of expanded text. `quoted` text is exempt from further processing,
+// and `split` marks the result of an unquoted expansion, which is subject to
+// field splitting. A `boundary` ends the current field, as between the
+// positional parameters of a quoted `$@`.
+struct Segment {
+    text: String,
+    quoted: bool,
+    split: bool,
+    boundary: bool,
+}
+
+fn lookup(shell: &Shell, param: &Param) -> Option<String> {
+    match param.name.as_str() {
+        "?" => Some(shell.last_status.to_string()),
+        "$" => Some(shell.pid.to_string()),
+        "!" => shell.last_background.map(|pid| pid.to_string()),
+        "-" => Some(shell.flags()),
+        "#" => Some(shell.positional.len().to_string()),
+        "@" | "*" => Some(shell.positional.join(" ")),
+        "0" => Some(shell.arg0.clone()),
+        digits if digits.starts_with(|c: char| c.is_ascii_digit()) => digits
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| n.checked_sub(1))
+            .and_then(|i| shell.positional.get(i).cloned()),
+        "PIPESTATUS" => {
+            let statuses: Vec<String> = shell.pipestatus.iter().map(|s| s.to_string()).collect();
+            match param.index.as_deref() {
+                Some("@") | Some("*") => Some(statuses.join(" ")),
+                Some(index) => index
+                    .trim()
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|i| statuses.get(i).cloned()),
+                None => statuses.first().cloned(),
+            }
+        }
+        name => match param.index.as_deref() {
+            None | Some("0") | Some("@") | Some("*") => shell.get_var(name).map(str::to_string),
+            Some(_) => None,
+        },
+    }
+}
+
+fn is_missing(value: &Option<String>, colon: bool) -> bool {
+    match value {
+        None => true,
+        Some(value) => colon && value.is_empty(),
+    }
+}
+
+// Builds a pattern from an expanded word; quoted parts match literally.
+pub fn expand_pattern(shell: &mut Shell, word: &Word) -> Result<Pattern, String> {
+    let mut segments = Vec::new();
+    expand_parts(shell, &word.parts, false, &mut segments)?;
+
+    let mut source = String::new();
+    for segment in segments {
+        if segment.quoted {
+            source.push_str(&pattern::escape(&segment.text));
+        } else {
+            source.push_str(&segment.text);
+        }
+    }
+    Ok(Pattern::new(&source))
+}
+
+fn remove_prefix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut ends: Vec<usize> = (0..=chars.len()).collect();
+    if longest {
+        ends.reverse();
+    }
+
+    match ends
+        .into_iter()
+        .find(|&end| pattern.matches_chars(&chars[..end]))
+    {
+        Some(end) => chars[end..].iter().collect(),
+        None => value.to_string(),
+    }
+}
+
+fn remove_suffix(value: &str, pattern: &Pattern, longest: bool) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let mut starts: Vec<usize> = (0..=chars.len()).collect();
+    if !longest {
+        starts.reverse();
+    }
+
+    match starts
+        .into_iter()
+        .find(|&start| pattern.matches_chars(&chars[start..]))
+    {
+        Some(start) => chars[..start].iter().collect(),
+        None => value.to_string(),
+    }
+}
+
+fn replace(value: &str, pattern: &Pattern, replacement: &str, mode: ReplaceMode) -> String {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len();
+    let text = |range: &[char]| range.iter().collect::<String>();
+
+    match mode {
+        ReplaceMode::Prefix => match (0..=len)
+            .rev()
+            .find(|&end| pattern.matches_chars(&chars[..end]))
+        {
+            Some(end) => format!("{}{}", replacement, text(&chars[end..])),
+            None => value.to_string(),
+        },
+        ReplaceMode::Suffix => {
+            match (0..=len).find(|&start| pattern.matches_chars(&chars[start..])) {
+                Some(start) => format!("{}{}", text(&chars[..start]), replacement),
+                None => value.to_string(),
+            }
+        }
+        ReplaceMode::First | ReplaceMode::All => {
+            let mut out = String::new();
+            let mut i = 0;
+
+            while i < len {
+                let end = (i + 1..=len)
+                    .rev()
+                    .find(|&end| pattern.matches_chars(&chars[i..end]));
+                match end {
+                    Some(end) => {
+                        out.push_str(replacement);
+                        i = end;
+                        if mode == ReplaceMode::First {
+                            out.push_str(&text(&chars[i..]));
+                            return out;
+                        }
+                    }
+                    None => {
+                        out.push(chars[i]);
+                        i += 1;
+                    }
+                }
+            }
+
+            out
+        }
+    }
+}
+
+fn substring(value: &str, offset: i64, length: Option<i64>) -> Result<String, String> {
+    let chars: Vec<char> = value.chars().collect();
+    let len = chars.len() as i64;
+
+    let start = if offset < 0 { len + offset } else { offset };
+    if start < 0 || start > len {
+        return Ok(String::new());
+    }
+
+    let end = match length {
+        None => len,
+        Some(length) if length < 0 => {
+            let end = len + length;
+            if end < start {
+                return Err(format!("{}: substring expression < 0", length));
+            }
+            end
+        }
+        Some(length) => (start + length).min(len),
+    };
+
+    Ok(chars[start as usize..end as usize].iter().collect())
+}
+
+// Expands an operand of a parameter expansion in place. Outside double
+// quotes its unquoted text is subject to field splitting.
+fn expand_operand(
+    shell: &mut Shell,
+    word: &Word,
+    quoted: bool,
+    out: &mut Vec<Segment>,
+) -> Result<(), String> {
+    let parts = if quoted {
+        word.parts.clone()
+    } else {
+        expand_tildes(shell, &word.parts, false)
+    };
+    let mut segments = Vec::new();
+    expand_parts(shell, &parts, quoted, &mut segments)?;
+    for mut segment in segments {
+        segment.split = !segment.quoted;
+        out.push(segment);
+    }
+    Ok(())
+}
+
+fn expand_param(
+    shell: &mut Shell,
+    param: &Param,
+    quoted: bool,
+    out: &mut Vec<Segment>,
+) -> Result<(), String> {
+    // Inside double quotes `$@` gives one field per positional parameter,
+    // while `$*` joins them with the first character of IFS.
+    if quoted && param.op == ParamOp::Plain && matches!(param.name.as_str(), "@" | "*") {
+        if param.name == "*" {
+            let ifs = shell.get_var("IFS").unwrap_or(DEFAULT_IFS);
+            let separator: String = ifs.chars().take(1).collect();
+            out.push(Segment {
+                text: shell.positional.join(&separator),
+                quoted,
+                split: false,
+                boundary: false,
+            });
+            return Ok(());
+        }
+
+        for (i, arg) in shell.positional.iter().enumerate() {
+            if i > 0 {
+                out.push(Segment {
+                    text: String::new(),
+                    quoted,
+                    split: false,
+                    boundary: true,
+                });
+            }
+            out.push(Segment {
+                text: arg.clone(),
+                quoted,
+                split: false,
+                boundary: false,
+            });
+        }
+        return Ok(());
+    }
+
+    let value = lookup(shell, param);
+    // Under nounset only the operators that test for unset parameters may
+    // see one. A script stops there.
+    let tested = matches!(
+        param.op,
+        ParamOp::Default(..) | ParamOp::Alternative(..) | ParamOp::Assign(..) | ParamOp::Error(..)
+    );
+    if value.is_none() && shell.options.nounset && !tested {
+        let message = format!("{}: unbound variable", param.name);
+        if !shell.interactive {
+            eprintln!("{}", message);
+            commands::exit_shell(shell, 1);
+        }
+        return Err(message);
+    }
+
+    let text = match &param.op {
+        ParamOp::Plain => value.unwrap_or_default(),
+        ParamOp::Length => match (param.name.as_str(), param.index.as_deref()) {
+            ("PIPESTATUS", Some("@" | "*")) => shell.pipestatus.len().to_string(),
+            _ => value.unwrap_or_default().chars().count().to_string(),
+        },
+        ParamOp::Default(colon, word) => {
+            if is_missing(&value, *colon) {
+                return expand_operand(shell, word, quoted, out);
+            }
+            value.unwrap_or_default()
+        }
+        ParamOp::Alternative(colon, word) => {
+            if !is_missing(&value, *colon) {
+                return expand_operand(shell, word, quoted, out);
+            }
+            String::new()
+        }
+        ParamOp::Assign(colon, word) => {
+            if is_missing(&value, *colon) {
+                if !lexer::is_name(&param.name) || param.index.is_some() {
+                    return Err(format!("{}: cannot assign in this way", param.source));
+                }
+                let assigned = expand_word(shell, word)?;
+                shell.set_var(&param.name, assigned.clone());
+                assigned
+            } else {
+                value.unwrap_or_default()
+            }
+        }
+        ParamOp::Error(colon, word) => {
+            if is_missing(&value, *colon) {
+                let message = expand_word(shell, word)?;
+                let message = match (message.is_empty(), colon) {
+                    (false, _) => message,
+                    (true, true) => "parameter null or not set".to_string(),
+                    (true, false) => "parameter not set".to_string(),
+                };
+                let message = format!("{}: {}", param.name, message);
+                // A script stops here.
+                if !shell.interactive {
+                    eprintln!("{}", message);
+                    commands::exit_shell(shell, 1);
+                }
+                return Err(message);
+            }
+            value.unwrap_or_default()
+        }
+        ParamOp::RemovePrefix(longest, word) => {
+            let pattern = expand_pattern(shell, word)?;
+            remove_prefix(&value.unwrap_or_default(), &pattern, *longest)
+        }
+        ParamOp::RemoveSuffix(longest, word) => {
+            let pattern = expand_pattern(shell, word)?;
+            remove_suffix(&value.unwrap_or_default(), &pattern, *longest)
+        }
+        ParamOp::Replace(mode, pattern, replacement) => {
+            let pattern = expand_pattern(shell, pattern)?;
+            let replacement = expand_word(shell, replacement)?;
+            replace(&value.unwrap_or_default(), &pattern, &replacement, *mode)
+        }
+        ParamOp::Substring(offset, length) => {
+            let offset = arith::eval(shell, offset)?;
+            let length = match length {
+                Some(length) => Some(arith::eval(shell, length)?),
+                None => None,
+            };
+            substring(&value.unwrap_or_default(), offset, length)?
+        }
+    };
+
+    out.push(Segment {
+        text,
+        quoted,
+        split: !quoted,
+        boundary: false,
+    });
+    Ok(())
+}
+
+fn expand_parts(
+    shell: &mut Shell,
+    parts: &[WordPart],
+    quoted: bool,
+    out: &mut Vec<Segment>,
+) -> Result<(), String> {
+    for part in parts {
+        let segment = match part {
+            WordPart::Literal(s) => Segment {
+                text: s.clone(),
+                quoted,
+                split: false,
+                boundary: false,
+            },
+            WordPart::SingleQuoted(s) => Segment {
+                text: s.clone(),
+                quoted: true,
+                split: false,
+                boundary: false,
+            },
+            WordPart::Escaped(c) => Segment {
+                text: c.to_string(),
+                quoted: true,
+                split: false,
+                boundary: false,
+            },
+            WordPart::DoubleQuoted(parts) => {
+                // A lone "$@" with no positional parameters vanishes entirely.
+                let empty_at = shell.positional.is_empty()
+                    && matches!(parts.as_slice(), [WordPart::Param(param)]
+                        if param.name == "@" && param.op == ParamOp::Plain);
+                if empty_at {
+                    continue;
+                }
+                out.push(Segment {
+                    text: String::new(),
+                    quoted: true,
+                    split: false,
+                    boundary: false,
+                });
+                expand_parts(shell, parts, true, out)?;
+                continue;
+            }
+            WordPart::Param(param) => {
+                expand_param(shell, param, quoted, out)?;
+                continue;
+            }
+            WordPart::Arith(parts) => {
+                let expression = expand_string(shell, parts)?;
+                Segment {
+                    text: arith::eval(shell, &expression)?.to_string(),
+                    quoted,
+                    split: !quoted,
+                    boundary: false,
+                }
+            }
+            WordPart::CommandSub(command) => Segment {
+                text: commands::substitute(command, shell)?,
+                quoted,
+                split: !quoted,
+                boundary: false,
+            },
+        };
+        out.push(segment);
+    }
+
+    Ok(())
+}
+
+// A field after splitting. `pattern` is the field as a glob pattern, with
+// its quoted characters escaped, and `glob` is set when an unquoted
+// character in it is special to pathname expansion.
+#[derive(Default)]
+struct Field {
+    text: String,
+    pattern: String,
+    glob: bool,
+}
+
+impl Field {
+    fn push(&mut self, c: char, quoted: bool) {
+        self.text.push(c);
+        if quoted {
+            self.pattern.push_str(&pattern::escape(&c.to_string()));
+        } else {
+            self.glob |= matches!(c, '*' | '?' | '[');
+            self.pattern.push(c);
+        }
+    }
+}
+
+// Splits expanded segments into fields on the characters of IFS. Whitespace
+// separators collapse into one, other separators each end a field.
+fn split_fields(segments: Vec<Segment>, ifs: &str) -> Vec<Field> {
+    let mut fields = Vec::new();
+    let mut current = Field::default();
+    let mut keep = false;
+    let mut after_blank = false;
+
+    for segment in segments {
+        if segment.boundary {
+            fields.push(std::mem::take(&mut current));
+            keep = false;
+            after_blank = false;
+            continue;
+        }
+
+        if !segment.split {
+            keep |= segment.quoted || !segment.text.is_empty();
+            after_blank &= segment.text.is_empty();
+            for c in segment.text.chars() {
+                current.push(c, segment.quoted);
+            }
+            continue;
+        }
+
+        for c in segment.text.chars() {
+            if !ifs.contains(c) {
+                current.push(c, false);
+                keep = true;
+                after_blank = false;
+            } else if c.is_whitespace() {
+                if keep {
+                    fields.push(std::mem::take(&mut current));
+                    keep = false;
+                    after_blank = true;
+                }
+            } else {
+                if keep || !after_blank {
+                    fields.push(std::mem::take(&mut current));
+                }
+                keep = false;
+                after_blank = false;
+            }
+        }
+    }
+
+    if keep {
+        fields.push(current);
+    }
+
+    fields
+}
+
+// The directory a tilde prefix stands for, if any.
+fn tilde_prefix(shell: &Shell, name: &str) -> Option<String> {
+    match name {
+        "" => match shell.get_var("HOME") {
+            Some(home) => Some(home.to_string()),
+            None => User::from_uid(Uid::current())
+                .ok()
+                .flatten()
+                .map(|user| user.dir.display().to_string()),
+        },
+        "+" => shell.get_var("PWD").map(str::to_string),
+        "-" => shell.get_var("OLDPWD").map(str::to_string),
+        _ => User::from_name(name)
+            .ok()
+            .flatten()
+            .map(|user| user.dir.display().to_string()),
+    }
+}
+
+// Performs tilde expansion at the start of a word and, in assignments, after
+// each unquoted `:`. A prefix is only expanded when it is entirely unquoted,
+// and the result is not subject to splitting or globbing.
+fn expand_tildes(shell: &Shell, parts: &[WordPart], assignment: bool) -> Vec<WordPart> {
+    let mut out = Vec::new();
+
+    for (i, part) in parts.iter().enumerate() {
+        let text = match part {
+            WordPart::Literal(text) if i == 0 || assignment => text,
+            _ => {
+                out.push(part.clone());
+                continue;
+            }
+        };
+        let ends_word = i == parts.len() - 1;
+        let mut literal = String::new();
+        let mut rest = text.as_str();
+        let mut at_start = i == 0;
+
+        loop {
+            if at_start && rest.starts_with('~') {
+                let end = rest
+                    .find(|c| c == '/' || (assignment && c == ':'))
+                    .unwrap_or(rest.len());
+                let dir = if end < rest.len() || ends_word {
+                    tilde_prefix(shell, &rest[1..end])
+                } else {
+                    None
+                };
+                if let Some(dir) = dir {
+                    if !literal.is_empty() {
+                        out.push(WordPart::Literal(std::mem::take(&mut literal)));
+                    }
+                    out.push(WordPart::SingleQuoted(dir));
+                    rest = &rest[end..];
+                }
+            }
+
+            match rest.find(':').filter(|_| assignment) {
+                Some(colon) => {
+                    literal.push_str(&rest[..=colon]);
+                    rest = &rest[colon + 1..];
+                    at_start = true;
+                }
+                None => {
+                    literal.push_str(rest);
+                    break;
+                }
+            }
+        }
+
+        if !literal.is_empty() {
+            out.push(WordPart::Literal(literal));
+        }
+    }
+
+    out
+}
+
+pub fn expand_string(shell: &mut Shell, parts: &[WordPart]) -> Result<String, String> {
+    let mut segments = Vec::new();
+    expand_parts(shell, parts, false, &mut segments)?;
+
+    let mut text = String::new();
+    for segment in segments {
+        if segment.boundary {
+            text.push(' ');
+        }
+        text.push_str(&segment.text);
+    }
+    Ok(text)
+}
+
+// Expands a word into a single string, without field splitting.
+pub fn expand_word(shell: &mut Shell, word: &Word) -> Result<String, String> {
+    let parts = expand_tildes(shell, &word.parts, false);
+    expand_string(shell, &parts)
+}
+
+// Expands the value of a `NAME=value` assignment.
+pub fn expand_assignment(shell: &mut Shell, word: &Word) -> Result<String, String> {
+    let parts = expand_tildes(shell, &word.parts, true);
+    expand_string(shell, &parts)
+}
+
+// Expands the words of a command into its fields.
+pub fn expand_words(shell: &mut Shell, words: &[Word]) -> Result<Vec<String>, String> {
+    let mut fields = Vec::new();
+
+    for word in words.iter().flat_map(brace::expand) {
+        let parts = expand_tildes(shell, &word.parts, false);
+        let mut segments = Vec::new();
+        expand_parts(shell, &parts, false, &mut segments)?;
+        let ifs = shell.get_var("IFS").unwrap_or(DEFAULT_IFS).to_string();
+
+        for field in split_fields(segments, &ifs) {
+            if !field.glob || shell.options.noglob {
+                fields.push(field.text);
+                continue;
+            }
+
+            let paths = glob::expand(&field.pattern, &shell.shopts);
+            if !paths.is_empty() {
+                fields.extend(paths);
+            } else if shell.shopts.failglob {
+                return Err(format!("no match: {}", field.text));
+            } else if !shell.shopts.nullglob {
+                fields.push(field.text);
+            }
+        }
+    }
+
+    Ok(fields)
+}
@@ -0,0 +1,124 @@
+// Pathname expansion. A pattern is matched one path component at a time;
+// literal components are only checked for existence.
+
+use std::fs;
+
+use crate::pattern::Pattern;
+use crate::shell::Shopts;
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else if dir.ends_with('/') {
+        format!("{}{}", dir, name)
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn open_dir(dir: &str) -> Option<fs::ReadDir> {
+    fs::read_dir(if dir.is_empty() { "." } else { dir }).ok()
+}
+
+fn is_dir(path: &str) -> bool {
+    fs::metadata(if path.is_empty() { "." } else { path }).is_ok_and(|meta| meta.is_dir())
+}
+
+// The entries of `dir` whose names match `pattern`. Names starting with a
+// dot are only matched when `hidden` is set.
+fn matching(dir: &str, pattern: &Pattern, hidden: bool) -> Vec<String> {
+    let mut paths = Vec::new();
+
+    for entry in open_dir(dir).into_iter().flatten().flatten() {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.starts_with('.') && !hidden {
+            continue;
+        }
+        if pattern.matches(&name) {
+            paths.push(join(dir, &name));
+        }
+    }
+
+    paths
+}
+
+// `dir` and everything below it, for `**`. Symbolic links to directories
+// are not followed.
+fn descend(dir: &str, hidden: bool, dirs_only: bool, out: &mut Vec<String>) {
+    for entry in open_dir(dir).into_iter().flatten().flatten() {
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        if name.starts_with('.') && !hidden {
+            continue;
+        }
+
+        let path = join(dir, &name);
+        let is_dir = entry.file_type().is_ok_and(|kind| kind.is_dir());
+        if is_dir || !dirs_only {
+            out.push(path.clone());
+        }
+        if is_dir {
+            descend(&path, hidden, dirs_only, out);
+        }
+    }
+}
+
+// Expands a pattern into the sorted list of paths it matches.
+pub fn expand(pattern: &str, options: &Shopts) -> Vec<String> {
+    let (mut paths, rest) = match pattern.strip_prefix('/') {
+        Some(rest) => (vec!["/".to_string()], rest),
+        None => (vec![String::new()], pattern),
+    };
+    let components: Vec<&str> = rest.split('/').collect();
+
+    for (i, component) in components.iter().enumerate() {
+        let last = i == components.len() - 1;
+
+        paths = if component.is_empty() {
+            paths
+                .into_iter()
+                .filter(|path| is_dir(path))
+                .map(|path| join(&path, ""))
+                .collect()
+        } else if options.globstar && *component == "**" {
+            let mut found = Vec::new();
+            for path in &paths {
+                if !last {
+                    found.push(path.clone());
+                }
+                descend(path, options.dotglob, !last, &mut found);
+            }
+            found
+        } else {
+            let pattern = Pattern::new(component);
+            match pattern.literal() {
+                Some(name) => paths
+                    .iter()
+                    .map(|path| join(path, &name))
+                    .filter(|path| fs::symlink_metadata(path).is_ok())
+                    .collect(),
+                None => {
+                    let hidden = options.dotglob
+                        || component.starts_with('.')
+                        || component.starts_with("\\.");
+                    paths
+                        .iter()
+                        .flat_map(|path| matching(path, &pattern, hidden))
+                        .collect()
+                }
+            }
+        };
+
+        if paths.is_empty() {
+            break;
+        }
+    }
+
+    paths.sort();
+    paths
+}
@@ -1,7 +1,14 @@
+use crate::ast::{List, Redirect, RedirectOp};
+use crate::expand;
+use crate::lexer::ParseError;
+use crate::shell::Shell;
+use crate::ShellCompleter;
+use nix::errno::Errno;
+use nix::unistd;
+use rustyline::error::ReadlineError;
 use rustyline::history::DefaultHistory;
 use rustyline::Editor;
 use std::collections::HashSet;
-use crate::ShellCompleter;
 
 pub fn get_executables() -> HashSet<String> {
     let mut executables = HashSet::new();
@@ -46,200 +53,165 @@ pub fn get_executables() -> HashSet<String> {
     executables
 }
 
-pub fn input(rl: &mut Editor<ShellCompleter, DefaultHistory>) -> Vec<String> {
-
-    let mut input = String::new();
-    let mut result = Vec::new();
-
-    let mut in_s_quotes = false;
-    let mut in_d_quotes = false;
-    loop {
-        let prompt = if input.is_empty() { "$ " } else { "> " };
-
-        let line = rl.readline(prompt).unwrap_or_else(|_| "".to_string());
+// Where commands are read from.
+pub enum Source {
+    // The terminal, through the line editor.
+    Prompt,
+    // Standard input, read a byte at a time so that the commands being run
+    // can read the rest of it.
+    Stdin,
+    // The text of a script or of `-c`, and how much of it has been read.
+    Text(String, usize),
+}
 
-        if !line.is_empty() {
-            rl.add_history_entry(&line).ok();
-        }
+// What reading a line came to.
+enum Line {
+    Read(String),
+    // Ctrl-C at the prompt.
+    Cancelled,
+    End,
+}
 
-        if !input.is_empty() {
-            input.clear();
-            input.push('\n');
-        }
-        input += line.trim();
-
-        let mut current = String::new();
-        let mut escape = false;
-        for char in input.chars() {
-            match char {
-                '\'' => {
-                    if !in_d_quotes && !escape {
-                        in_s_quotes = !in_s_quotes;
-                    } else {
-                        current.push(char);
-                        escape = false;
-                    }
+impl Source {
+    fn read_line(
+        &mut self,
+        rl: &mut Editor<ShellCompleter, DefaultHistory>,
+        continued: bool,
+    ) -> Line {
+        match self {
+            Source::Prompt => {
+                let prompt = if continued { "> " } else { "$ " };
+                let line = match rl.readline(prompt) {
+                    Ok(line) => line,
+                    Err(ReadlineError::Interrupted) => return Line::Cancelled,
+                    Err(_) => return Line::End,
+                };
+
+                if !line.is_empty() {
+                    rl.add_history_entry(&line).ok();
                 }
-                '"' => {
-                    if !in_s_quotes && !escape {
-                        in_d_quotes = !in_d_quotes;
-                    } else {
-                        if escape && in_d_quotes {
-                            current.pop();
-                        }
-                        current.push(char);
-                        escape = false;
-                    }
-                }
-                '\\' => {
-                    if in_s_quotes {
-                        current.push(char);
-                    } else if in_d_quotes && escape {
-                        escape = false;
-                    } else if !escape && in_d_quotes {
-                        current.push(char);
-                        escape = true;
-                    } else {
-                        escape = true;
-                    }
-                }
-                ' ' => {
-                    if !in_s_quotes && !in_d_quotes && !escape {
-                        if !current.is_empty() {
-                            result.push(current.clone());
-                            current.clear();
-                        }
-                    } else {
-                        current.push(char);
-                        escape = false;
+                Line::Read(line)
+            }
+            Source::Stdin => {
+                let mut line = Vec::new();
+                let mut byte = [0u8];
+                loop {
+                    match unistd::read(0, &mut byte) {
+                        Ok(1) if byte[0] == b'\n' => break,
+                        Ok(1) => line.push(byte[0]),
+                        Err(Errno::EINTR) => continue,
+                        _ if line.is_empty() => return Line::End,
+                        _ => break,
                     }
                 }
-                _ => {
-                    current.push(char);
-                    escape = false;
+                Line::Read(String::from_utf8_lossy(&line).into_owned())
+            }
+            Source::Text(text, pos) => {
+                let rest = &text[*pos..];
+                if rest.is_empty() {
+                    return Line::End;
                 }
+                let line = rest.split('\n').next().unwrap_or_default();
+                *pos = (*pos + line.len() + 1).min(text.len());
+                Line::Read(line.to_string())
             }
         }
-
-        if !current.is_empty() {
-            result.push(current);
-        }
-
-        if !in_s_quotes && !in_d_quotes {
-            break;
-        }
     }
-
-    result
 }
 
-pub fn split_inputs(mut input_lines: Vec<String>) -> (Vec<String>, Vec<Vec<String>>) {
-    let mut cmds: Vec<String> = Vec::new();
-    let mut cmd_args = Vec::new();
-    let mut args = Vec::new();
-
-    cmds.push(input_lines.remove(0));
-
-    let mut last_el_is_pipe = false;
+// Reads lines until they make up complete commands. Returns None at the end
+// of the input. Ctrl-C throws away what has been typed so far, which makes
+// an empty list.
+pub fn input(shell: &mut Shell, source: &mut Source) -> Option<Result<List, ParseError>> {
+    let mut input = String::new();
 
-    for el in input_lines {
-        match el.as_str() {
-            "|" => {
-                args.push(cmd_args.clone());
-                cmd_args.clear();
-                cmd_args.push("".to_string());
-                last_el_is_pipe = true;
-            }
-            _ => {
-                if last_el_is_pipe {
-                    cmds.push(el);
-                    last_el_is_pipe = false;
-                } else {
-                    cmd_args.push(el);
-                }
+    loop {
+        let line = match source.read_line(&mut shell.rl, !input.is_empty()) {
+            Line::Read(line) => line,
+            Line::Cancelled => {
+                shell.last_status = 130;
+                return Some(Ok(List::default()));
             }
-        }
-    }
+            Line::End if input.is_empty() => return None,
+            Line::End => return Some(Err(ParseError::Incomplete)),
+        };
 
-    if !cmd_args.is_empty() {
-        args.push(cmd_args);
-    }
+        input += &line;
+        input.push('\n');
 
-    if args.is_empty() {
-        args.push(vec![])
+        match shell.parse(&input) {
+            Err(ParseError::Incomplete) => continue,
+            result => return Some(result),
+        }
     }
-
-    (cmds, args)
 }
 
-pub enum OutputMode {
-    Default,
-    File,
-    FileAppend,
+pub enum OpenMode {
+    Read,
+    Write,
+    // Writing under noclobber, which leaves existing files alone.
+    WriteNew,
+    Append,
+    ReadWrite,
 }
 
-pub struct OutputConf {
-    pub std_out: String,
-    pub std_out_mode: OutputMode,
-    pub std_err: String,
-    pub std_err_mode: OutputMode,
+// A single redirection step; a command's steps are applied left to right.
+pub enum FdOp {
+    Open(i32, String, OpenMode),
+    Text(i32, String),
+    Dup(i32, i32),
+    Close(i32),
 }
 
-pub fn redirection(mut args: Vec<String>) -> (Vec<String>, OutputConf) {
-    let mut output_conf = OutputConf {
-        std_out: "".to_string(),
-        std_out_mode: OutputMode::Default,
-        std_err: "".to_string(),
-        std_err_mode: OutputMode::Default,
-    };
-
-    let mut i = 0;
-    while i < args.len() {
-        match args[i].as_str() {
-            "1>" | ">" => {
-                if i + 1 < args.len() {
-                    output_conf.std_out = args[i + 1].clone();
-                    output_conf.std_out_mode = OutputMode::File;
-                    args.remove(i + 1);
-                } else {
-                    eprintln!("syntax error");
-                }
-                args.remove(i);
-            }
-            "2>" => {
-                if i + 1 < args.len() {
-                    output_conf.std_err = args[i + 1].clone();
-                    output_conf.std_err_mode = OutputMode::File;
-                    args.remove(i + 1);
-                } else {
-                    eprintln!("syntax error");
-                }
-                args.remove(i);
-            }
-            "1>>" | ">>" => {
-                if i + 1 < args.len() {
-                    output_conf.std_out = args[i + 1].clone();
-                    output_conf.std_out_mode = OutputMode::FileAppend;
-                    args.remove(i + 1);
-                } else {
-                    eprintln!("syntax error");
-                }
-                args.remove(i);
+pub fn redirection(shell: &mut Shell, redirects: &[Redirect]) -> Result<Vec<FdOp>, String> {
+    let mut ops = Vec::new();
+
+    for redirect in redirects {
+        let target = expand::expand_word(shell, &redirect.target)?;
+        let default_fd = match redirect.op {
+            RedirectOp::Read
+            | RedirectOp::ReadWrite
+            | RedirectOp::DupIn
+            | RedirectOp::HereString
+            | RedirectOp::HereDoc => 0,
+            _ => 1,
+        };
+        let fd = redirect.fd.unwrap_or(default_fd);
+        let write = || match shell.options.noclobber {
+            true => OpenMode::WriteNew,
+            false => OpenMode::Write,
+        };
+
+        match redirect.op {
+            RedirectOp::Read => ops.push(FdOp::Open(fd, target, OpenMode::Read)),
+            RedirectOp::Write => ops.push(FdOp::Open(fd, target, write())),
+            RedirectOp::Clobber => ops.push(FdOp::Open(fd, target, OpenMode::Write)),
+            RedirectOp::Append => ops.push(FdOp::Open(fd, target, OpenMode::Append)),
+            RedirectOp::ReadWrite => ops.push(FdOp::Open(fd, target, OpenMode::ReadWrite)),
+            RedirectOp::HereString => ops.push(FdOp::Text(fd, target + "\n")),
+            RedirectOp::HereDoc => ops.push(FdOp::Text(fd, target)),
+            RedirectOp::WriteAll | RedirectOp::AppendAll => {
+                let mode = match redirect.op {
+                    RedirectOp::WriteAll => write(),
+                    _ => OpenMode::Append,
+                };
+                ops.push(FdOp::Open(1, target, mode));
+                ops.push(FdOp::Dup(2, 1));
             }
-            "2>>" => {
-                if i + 1 < args.len() {
-                    output_conf.std_err = args[i + 1].clone();
-                    output_conf.std_err_mode = OutputMode::FileAppend;
-                    args.remove(i + 1);
+            RedirectOp::DupOut | RedirectOp::DupIn => {
+                if target == "-" {
+                    ops.push(FdOp::Close(fd));
+                } else if let Ok(source) = target.parse() {
+                    ops.push(FdOp::Dup(fd, source));
+                } else if redirect.op == RedirectOp::DupOut && redirect.fd.is_none() {
+                    ops.push(FdOp::Open(1, target, write()));
+                    ops.push(FdOp::Dup(2, 1));
                 } else {
-                    eprintln!("syntax error");
+                    return Err(format!("{}: ambiguous redirect", target));
                 }
-                args.remove(i);
             }
-            _ => {}
         }
-        i += 1;
     }
 
-    (args, output_conf)
+    Ok(ops)
 }
@@ -0,0 +1,401 @@
+// The job table and the process groups and terminal handling behind job
+// control. Job control is only on in an interactive shell on a terminal;
+// background jobs are tracked either way so that `wait` and `jobs` work in
+// scripts too.
+
+use std::os::fd::RawFd;
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg};
+use nix::sys::signal::{killpg, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{self, Pid};
+
+use crate::redirect::HIGH_FD;
+use crate::shell::Shell;
+use crate::signals;
+
+// Signals that would stop the shell itself, which it ignores while job
+// control is on.
+const JOB_SIGNALS: [Signal; 3] = [Signal::SIGTSTP, Signal::SIGTTIN, Signal::SIGTTOU];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    Running,
+    // Stopped by the given signal.
+    Stopped(i32),
+    Exited(i32),
+    Killed(i32),
+}
+
+impl State {
+    pub fn status(self) -> i32 {
+        match self {
+            State::Running => 0,
+            State::Exited(code) => code,
+            State::Stopped(signal) | State::Killed(signal) => 128 + signal,
+        }
+    }
+}
+
+// A process of a job; the stages of a pipeline that ran inside the shell
+// have no pid.
+pub struct Process {
+    pub pid: Option<Pid>,
+    pub state: State,
+}
+
+pub struct Job {
+    pub id: usize,
+    pub pgid: Option<Pid>,
+    pub processes: Vec<Process>,
+    pub text: String,
+    // When the job was last started or stopped, which decides the current
+    // and previous jobs.
+    pub touched: u64,
+    // Whether the job stopping has been reported.
+    pub notified: bool,
+}
+
+impl Job {
+    pub fn state(&self) -> State {
+        let mut states = self.processes.iter().map(|process| process.state);
+        if let Some(stopped) = states
+            .clone()
+            .find(|state| matches!(state, State::Stopped(_)))
+        {
+            return stopped;
+        }
+        if states.clone().any(|state| state == State::Running) {
+            return State::Running;
+        }
+        states.next_back().unwrap_or(State::Exited(0))
+    }
+
+    pub fn finished(&self) -> bool {
+        matches!(self.state(), State::Exited(_) | State::Killed(_))
+    }
+
+    // The process group, or the process when there is none.
+    pub fn pid(&self) -> Option<Pid> {
+        self.pgid
+            .or_else(|| self.processes.iter().find_map(|process| process.pid))
+    }
+}
+
+// How the processes of the job being started are grouped: the first one
+// leads a new group that the others join. A foreground job is given the
+// terminal.
+#[derive(Clone, Copy)]
+pub struct Group {
+    pub pgid: Option<Pid>,
+    pub terminal: Option<RawFd>,
+}
+
+// Turns on job control if the shell reads from a terminal: waits until it is
+// in the foreground, then puts it in a process group of its own.
+pub fn enable(shell: &mut Shell) {
+    let terminal = match fcntl(0, FcntlArg::F_DUPFD_CLOEXEC(HIGH_FD)) {
+        Ok(terminal) if unistd::isatty(terminal).unwrap_or(false) => terminal,
+        Ok(terminal) => {
+            unistd::close(terminal).ok();
+            return;
+        }
+        Err(_) => return,
+    };
+
+    loop {
+        match unistd::tcgetpgrp(terminal) {
+            Ok(pgid) if pgid == unistd::getpgrp() => break,
+            Ok(_) => {
+                killpg(unistd::getpgrp(), Signal::SIGTTIN).ok();
+            }
+            Err(_) => return,
+        }
+    }
+
+    signals::ignore(shell, &JOB_SIGNALS);
+    let pid = unistd::getpid();
+    unistd::setpgid(pid, pid).ok();
+    unistd::tcsetpgrp(terminal, pid).ok();
+
+    shell.terminal = terminal;
+    shell.job_control = true;
+}
+
+// Puts a new process in its job's group, if there is one, taking the
+// terminal for a foreground job. Runs in the child before it does anything
+// else.
+pub fn setup_child(group: Option<Group>) {
+    if let Some(group) = group {
+        unistd::setpgid(Pid::from_raw(0), group.pgid.unwrap_or(Pid::from_raw(0))).ok();
+        if let Some(terminal) = group.terminal {
+            unistd::tcsetpgrp(terminal, unistd::getpgrp()).ok();
+        }
+    }
+}
+
+// Records a process the shell has started as part of the current group. The
+// parent sets the group as well, so that it is settled whichever side runs
+// first.
+pub fn started(shell: &mut Shell, pid: Pid) {
+    if let Some(group) = &mut shell.group {
+        let pgid = *group.pgid.get_or_insert(pid);
+        unistd::setpgid(pid, pgid).ok();
+        if let Some(terminal) = group.terminal {
+            unistd::tcsetpgrp(terminal, pgid).ok();
+        }
+    }
+}
+
+// Takes the terminal back once a foreground job has finished or stopped.
+pub fn reclaim_terminal(shell: &Shell) {
+    if shell.job_control {
+        unistd::tcsetpgrp(shell.terminal, unistd::getpgrp()).ok();
+    }
+}
+
+// Waits for a process to change state. Without blocking, returns None if it
+// hasn't.
+pub fn wait_process(pid: Pid, flags: WaitPidFlag) -> Option<State> {
+    loop {
+        match waitpid(pid, Some(flags)) {
+            Ok(WaitStatus::Exited(_, code)) => return Some(State::Exited(code)),
+            Ok(WaitStatus::Signaled(_, signal, _)) => return Some(State::Killed(signal as i32)),
+            Ok(WaitStatus::Stopped(_, signal)) => return Some(State::Stopped(signal as i32)),
+            Ok(WaitStatus::StillAlive) => return None,
+            Ok(_) | Err(Errno::EINTR) => continue,
+            Err(_) => return Some(State::Exited(127)),
+        }
+    }
+}
+
+// Waits for the processes of a foreground job until each has finished or
+// stopped.
+pub fn wait_foreground(shell: &Shell, processes: &mut [Process]) {
+    for process in processes.iter_mut() {
+        if let (Some(pid), State::Running) = (process.pid, process.state) {
+            process.state = wait_process(pid, WaitPidFlag::WUNTRACED).unwrap_or(State::Running);
+        }
+    }
+    if processes.iter().any(|process| process.pid.is_some()) {
+        reclaim_terminal(shell);
+    }
+}
+
+// Adds a job to the table, numbered one past the highest job number in use.
+pub fn add(shell: &mut Shell, pgid: Option<Pid>, processes: Vec<Process>, text: String) -> usize {
+    let id = shell.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1;
+    shell.parent_jobs = None;
+    shell.job_clock += 1;
+    shell.jobs.push(Job {
+        id,
+        pgid,
+        processes,
+        text,
+        touched: shell.job_clock,
+        notified: false,
+    });
+    id
+}
+
+pub fn touch(shell: &mut Shell, index: usize) {
+    shell.job_clock += 1;
+    shell.jobs[index].touched = shell.job_clock;
+}
+
+// The numbers of the current and previous jobs. Stopped jobs come before
+// running ones, then the most recently started or stopped.
+fn current(jobs: &[Job]) -> (Option<usize>, Option<usize>) {
+    let mut order: Vec<&Job> = jobs.iter().collect();
+    order.sort_by_key(|job| {
+        let stopped = matches!(job.state(), State::Stopped(_));
+        std::cmp::Reverse((stopped, job.touched))
+    });
+    (
+        order.first().map(|job| job.id),
+        order.get(1).map(|job| job.id),
+    )
+}
+
+// Finds a job by a `%` job spec or, without one, the current job. Returns
+// its index in the table.
+pub fn find(jobs: &[Job], spec: Option<&str>) -> Result<usize, String> {
+    let (current, previous) = current(jobs);
+    let id = match spec {
+        None | Some("%" | "%%" | "%+") => current.ok_or("current: no such job".to_string())?,
+        Some("%-") => previous.ok_or("previous: no such job".to_string())?,
+        Some(spec) => {
+            let text = spec.strip_prefix('%').unwrap_or(spec);
+            let found = match text.parse::<usize>() {
+                Ok(id) => jobs.iter().find(|job| job.id == id),
+                Err(_) => match text.strip_prefix('?') {
+                    Some(text) => jobs.iter().find(|job| job.text.contains(text)),
+                    None => jobs.iter().find(|job| job.text.starts_with(text)),
+                },
+            };
+            found.ok_or(format!("{}: no such job", spec))?.id
+        }
+    };
+
+    Ok(jobs.iter().position(|job| job.id == id).unwrap_or(0))
+}
+
+pub fn signal_name(signal: i32) -> String {
+    match signal {
+        1 => "Hangup".to_string(),
+        2 => "Interrupt".to_string(),
+        3 => "Quit".to_string(),
+        6 => "Aborted".to_string(),
+        9 => "Killed".to_string(),
+        11 => "Segmentation fault".to_string(),
+        13 => "Broken pipe".to_string(),
+        15 => "Terminated".to_string(),
+        _ => match Signal::try_from(signal) {
+            Ok(signal) => signal.as_str().to_string(),
+            Err(_) => format!("Signal {}", signal),
+        },
+    }
+}
+
+// `+` for the current job and `-` for the previous one.
+pub fn marker(jobs: &[Job], index: usize) -> char {
+    let id = jobs[index].id;
+    match current(jobs) {
+        (Some(current), _) if current == id => '+',
+        (_, Some(previous)) if previous == id => '-',
+        _ => ' ',
+    }
+}
+
+// A line of `jobs` output, which is also how changes are reported.
+pub fn describe(jobs: &[Job], index: usize, long: bool) -> String {
+    let job = &jobs[index];
+    let marker = marker(jobs, index);
+
+    let (state, suffix) = match job.state() {
+        State::Running => ("Running".to_string(), " &"),
+        State::Stopped(_) => ("Stopped".to_string(), ""),
+        State::Exited(0) => ("Done".to_string(), ""),
+        State::Exited(code) => (format!("Exit {}", code), ""),
+        State::Killed(signal) => (signal_name(signal), ""),
+    };
+
+    let pid = match (long, job.pid()) {
+        (true, Some(pid)) => format!(" {} ", pid),
+        _ => "  ".to_string(),
+    };
+    format!(
+        "[{}]{}{}{:<24}{}{}",
+        job.id, marker, pid, state, job.text, suffix
+    )
+}
+
+// Collects the state changes of background processes without blocking.
+pub fn update(shell: &mut Shell) {
+    let flags = WaitPidFlag::WNOHANG | WaitPidFlag::WUNTRACED | WaitPidFlag::WCONTINUED;
+    for job in &mut shell.jobs {
+        for process in &mut job.processes {
+            let pid = match (process.pid, process.state) {
+                (Some(pid), State::Running | State::Stopped(_)) => pid,
+                _ => continue,
+            };
+            match waitpid(pid, Some(flags)) {
+                Ok(WaitStatus::Exited(_, code)) => process.state = State::Exited(code),
+                Ok(WaitStatus::Signaled(_, signal, _)) => {
+                    process.state = State::Killed(signal as i32)
+                }
+                Ok(WaitStatus::Stopped(_, signal)) => {
+                    process.state = State::Stopped(signal as i32);
+                    job.notified = false;
+                }
+                Ok(WaitStatus::Continued(_)) => process.state = State::Running,
+                Ok(_) => {}
+                Err(_) => process.state = State::Exited(127),
+            }
+        }
+    }
+}
+
+// Reports jobs that have finished or stopped since the last prompt, and
+// forgets the finished ones.
+pub fn notify(shell: &mut Shell) {
+    update(shell);
+
+    let mut index = 0;
+    while index < shell.jobs.len() {
+        let job = &shell.jobs[index];
+        match job.state() {
+            State::Running => {}
+            State::Stopped(_) if job.notified => {}
+            State::Stopped(_) => {
+                eprintln!("{}", describe(&shell.jobs, index, false));
+                shell.jobs[index].notified = true;
+            }
+            _ => {
+                eprintln!("{}", describe(&shell.jobs, index, false));
+                shell.jobs.remove(index);
+                continue;
+            }
+        }
+        index += 1;
+    }
+}
+
+// Continues a job in the foreground and waits for it. A job that stops again
+// stays in the table. Returns its status.
+pub fn foreground(shell: &mut Shell, index: usize) -> i32 {
+    let pgid = shell.jobs[index].pgid;
+    if let Some(pgid) = pgid {
+        unistd::tcsetpgrp(shell.terminal, pgid).ok();
+        killpg(pgid, Signal::SIGCONT).ok();
+    }
+
+    let job = &mut shell.jobs[index];
+    for process in &mut job.processes {
+        if let State::Stopped(_) = process.state {
+            process.state = State::Running;
+        }
+    }
+    let mut processes = std::mem::take(&mut job.processes);
+    wait_foreground(shell, &mut processes);
+    shell.jobs[index].processes = processes;
+
+    let state = shell.jobs[index].state();
+    if let State::Stopped(_) = state {
+        touch(shell, index);
+        shell.jobs[index].notified = true;
+        eprintln!("\n{}", describe(&shell.jobs, index, false));
+    } else {
+        shell.jobs.remove(index);
+    }
+    state.status()
+}
+
+// Continues a stopped job in the background.
+pub fn background(shell: &mut Shell, index: usize) {
+    if let Some(pgid) = shell.jobs[index].pgid {
+        killpg(pgid, Signal::SIGCONT).ok();
+    }
+    for process in &mut shell.jobs[index].processes {
+        if let State::Stopped(_) = process.state {
+            process.state = State::Running;
+        }
+    }
+    touch(shell, index);
+}
+
+// Waits for every process of a job to finish, then forgets the job.
+// Returns its status.
+pub fn wait_job(shell: &mut Shell, index: usize) -> i32 {
+    for process in &mut shell.jobs[index].processes {
+        if let (Some(pid), State::Running) = (process.pid, process.state) {
+            process.state = wait_process(pid, WaitPidFlag::empty()).unwrap_or(State::Running);
+        }
+    }
+    let status = shell.jobs[index].state().status();
+    if !matches!(shell.jobs[index].state(), State::Stopped(_)) {
+        shell.jobs.remove(index);
+    }
+    status
+}
@@ -0,0 +1,960 @@
+use crate::ast::{push_literal, Param, ParamOp, RedirectOp, ReplaceMode, Word, WordPart};
+use crate::parser;
+use thiserror::Error;
+
+#[derive(Debug, Error, PartialEq)]
+pub enum ParseError {
+    #[error("unexpected end of input")]
+    Incomplete,
+    #[error("syntax error near unexpected token `{0}'")]
+    Unexpected(String),
+    #[error("{0}: bad substitution")]
+    BadSubstitution(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Word(Word),
+    Pipe,
+    AndIf,
+    OrIf,
+    Semi,
+    // A lone `&`, which runs the command before it in the background.
+    Amp,
+    DSemi,
+    LParen,
+    RParen,
+    Redirect(Option<i32>, RedirectOp),
+    HereDoc(Option<i32>, Word),
+    // A `((...))` command.
+    Arith(Word),
+    Newline,
+}
+
+impl Token {
+    pub fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => word.text(),
+            Token::Pipe => "|".to_string(),
+            Token::AndIf => "&&".to_string(),
+            Token::OrIf => "||".to_string(),
+            Token::Semi => ";".to_string(),
+            Token::Amp => "&".to_string(),
+            Token::DSemi => ";;".to_string(),
+            Token::LParen => "(".to_string(),
+            Token::RParen => ")".to_string(),
+            Token::Redirect(_, op) => op.symbol().to_string(),
+            Token::HereDoc(..) => RedirectOp::HereDoc.symbol().to_string(),
+            Token::Arith(_) => "((".to_string(),
+            Token::Newline => "newline".to_string(),
+        }
+    }
+}
+
+// A here-document whose body starts after the next newline.
+struct PendingHereDoc {
+    token: usize,
+    delimiter: String,
+    strip_tabs: bool,
+    quoted: bool,
+}
+
+struct Lexer {
+    chars: Vec<char>,
+    pos: usize,
+    heredocs: Vec<PendingHereDoc>,
+}
+
+impl Lexer {
+    fn new(src: &str) -> Lexer {
+        Lexer {
+            chars: src.chars().collect(),
+            pos: 0,
+            heredocs: Vec::new(),
+        }
+    }
+}
+
+pub fn tokenize(src: &str) -> Result<Vec<Token>, ParseError> {
+    let mut lexer = Lexer::new(src);
+    let mut tokens = Vec::new();
+
+    while lexer.read_token(&mut tokens)? {}
+
+    if !lexer.heredocs.is_empty() {
+        return Err(ParseError::Incomplete);
+    }
+
+    Ok(tokens)
+}
+
+impl Lexer {
+    // Adds the next token to `tokens`, along with the bodies of the
+    // here-documents that a newline token completes. Returns false at the
+    // end of the input.
+    fn read_token(&mut self, tokens: &mut Vec<Token>) -> Result<bool, ParseError> {
+        let token = match self.next_token(tokens.len())? {
+            Some(token) => token,
+            None => return Ok(false),
+        };
+        let newline = token == Token::Newline;
+        tokens.push(token);
+
+        if newline {
+            for heredoc in std::mem::take(&mut self.heredocs) {
+                let body = self.heredoc_body(&heredoc)?;
+                if let Token::HereDoc(_, word) = &mut tokens[heredoc.token] {
+                    *word = body;
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, offset: usize) -> Option<char> {
+        self.chars.get(self.pos + offset).copied()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek();
+        if c.is_some() {
+            self.pos += 1;
+        }
+        c
+    }
+
+    // A backslash-newline that ends the input continues the line, so the
+    // next one has to be read first.
+    fn continued_line(&self) -> bool {
+        self.peek() == Some('\\')
+            && self.peek_at(1) == Some('\n')
+            && self.pos + 2 == self.chars.len()
+    }
+
+    fn skip_blanks(&mut self) -> Result<(), ParseError> {
+        loop {
+            match self.peek() {
+                Some(' ') | Some('\t') => self.pos += 1,
+                _ if self.continued_line() => return Err(ParseError::Incomplete),
+                Some('\\') if self.peek_at(1) == Some('\n') => self.pos += 2,
+                Some('#') => {
+                    while !matches!(self.peek(), None | Some('\n')) {
+                        self.pos += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        Ok(())
+    }
+
+    fn next_token(&mut self, index: usize) -> Result<Option<Token>, ParseError> {
+        self.skip_blanks()?;
+
+        let c = match self.peek() {
+            Some(c) => c,
+            None => return Ok(None),
+        };
+
+        let token = match c {
+            '\n' => {
+                self.bump();
+                Token::Newline
+            }
+            '|' => {
+                self.bump();
+                if self.peek() == Some('|') {
+                    self.bump();
+                    Token::OrIf
+                } else {
+                    Token::Pipe
+                }
+            }
+            '&' if self.peek_at(1) == Some('&') => {
+                self.pos += 2;
+                Token::AndIf
+            }
+            '&' if self.peek_at(1) == Some('>') => {
+                self.pos += 2;
+                if self.peek() == Some('>') {
+                    self.bump();
+                    Token::Redirect(None, RedirectOp::AppendAll)
+                } else {
+                    Token::Redirect(None, RedirectOp::WriteAll)
+                }
+            }
+            '&' => {
+                self.bump();
+                Token::Amp
+            }
+            ';' if self.peek_at(1) == Some(';') => {
+                self.pos += 2;
+                Token::DSemi
+            }
+            ';' => {
+                self.bump();
+                Token::Semi
+            }
+            '>' | '<' => self.redirect(None, index)?,
+            '(' if self.peek_at(1) == Some('(') => {
+                let start = self.pos;
+                self.pos += 2;
+                match self.arith_expression()? {
+                    Some(parts) => Token::Arith(Word { parts }),
+                    // Two subshells opened at once.
+                    None => {
+                        self.pos = start + 1;
+                        Token::LParen
+                    }
+                }
+            }
+            '(' => {
+                self.bump();
+                Token::LParen
+            }
+            ')' => {
+                self.bump();
+                Token::RParen
+            }
+            _ => {
+                let word = self.word()?;
+                match (word.as_unquoted(), self.peek()) {
+                    (Some(digits), Some('>' | '<'))
+                        if digits.chars().all(|c| c.is_ascii_digit()) =>
+                    {
+                        match digits.parse() {
+                            Ok(fd) => self.redirect(Some(fd), index)?,
+                            Err(_) => Token::Word(word),
+                        }
+                    }
+                    _ => Token::Word(word),
+                }
+            }
+        };
+
+        Ok(Some(token))
+    }
+
+    fn at_operator(&self) -> bool {
+        matches!(self.peek(), Some('|' | '&' | '>' | '<' | ';' | '(' | ')'))
+    }
+
+    fn redirect(&mut self, fd: Option<i32>, index: usize) -> Result<Token, ParseError> {
+        if self.bump() == Some('>') {
+            let op = match self.peek() {
+                Some('>') => RedirectOp::Append,
+                Some('&') => RedirectOp::DupOut,
+                Some('|') => RedirectOp::Clobber,
+                _ => return Ok(Token::Redirect(fd, RedirectOp::Write)),
+            };
+            self.bump();
+            return Ok(Token::Redirect(fd, op));
+        }
+
+        let op = match self.peek() {
+            Some('<') => RedirectOp::HereDoc,
+            Some('&') => RedirectOp::DupIn,
+            Some('>') => RedirectOp::ReadWrite,
+            _ => return Ok(Token::Redirect(fd, RedirectOp::Read)),
+        };
+        self.bump();
+        if op != RedirectOp::HereDoc {
+            return Ok(Token::Redirect(fd, op));
+        }
+
+        match self.peek() {
+            Some('<') => {
+                self.bump();
+                Ok(Token::Redirect(fd, RedirectOp::HereString))
+            }
+            _ => {
+                let strip_tabs = self.peek() == Some('-');
+                if strip_tabs {
+                    self.bump();
+                }
+                self.heredoc(fd, strip_tabs, index)
+            }
+        }
+    }
+
+    fn heredoc(
+        &mut self,
+        fd: Option<i32>,
+        strip_tabs: bool,
+        index: usize,
+    ) -> Result<Token, ParseError> {
+        self.skip_blanks()?;
+        if matches!(self.peek(), None | Some('\n')) || self.at_operator() {
+            let next = self.next_token(index)?;
+            return Err(ParseError::Unexpected(
+                next.map_or("newline".to_string(), |token| token.describe()),
+            ));
+        }
+
+        let delimiter = self.word()?;
+        let quoted = delimiter
+            .parts
+            .iter()
+            .any(|part| !matches!(part, WordPart::Literal(_)));
+
+        self.heredocs.push(PendingHereDoc {
+            token: index,
+            delimiter: delimiter.text(),
+            strip_tabs,
+            quoted,
+        });
+
+        Ok(Token::HereDoc(fd, Word::default()))
+    }
+
+    fn heredoc_body(&mut self, heredoc: &PendingHereDoc) -> Result<Word, ParseError> {
+        let mut body = String::new();
+
+        loop {
+            let start = self.pos;
+            while self.peek().is_some_and(|c| c != '\n') {
+                self.pos += 1;
+            }
+            if self.bump().is_none() {
+                return Err(ParseError::Incomplete);
+            }
+
+            let line: String = self.chars[start..self.pos - 1].iter().collect();
+            let line = if heredoc.strip_tabs {
+                line.trim_start_matches('\t')
+            } else {
+                &line
+            };
+            if line == heredoc.delimiter {
+                break;
+            }
+            body.push_str(line);
+            body.push('\n');
+        }
+
+        if heredoc.quoted {
+            return Ok(Word {
+                parts: vec![WordPart::SingleQuoted(body)],
+            });
+        }
+
+        let parts = Lexer::new(&body).quoted_parts(true)?;
+        Ok(Word {
+            parts: vec![WordPart::DoubleQuoted(parts)],
+        })
+    }
+
+    fn word(&mut self) -> Result<Word, ParseError> {
+        let mut word = Word::default();
+
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\n' => break,
+                _ if self.at_operator() => break,
+                '\'' => {
+                    self.bump();
+                    let mut quoted = String::new();
+                    loop {
+                        match self.bump() {
+                            Some('\'') => break,
+                            Some(c) => quoted.push(c),
+                            None => return Err(ParseError::Incomplete),
+                        }
+                    }
+                    word.parts.push(WordPart::SingleQuoted(quoted));
+                }
+                '"' => {
+                    self.bump();
+                    let parts = self.quoted_parts(false)?;
+                    word.parts.push(WordPart::DoubleQuoted(parts));
+                }
+                '\\' if self.continued_line() => return Err(ParseError::Incomplete),
+                '\\' => {
+                    self.bump();
+                    match self.bump() {
+                        Some('\n') => {}
+                        Some(c) => word.parts.push(WordPart::Escaped(c)),
+                        None => return Err(ParseError::Incomplete),
+                    }
+                }
+                '$' => match self.dollar()? {
+                    Some(part) => word.parts.push(part),
+                    None => {
+                        self.bump();
+                        word.push_char('$');
+                    }
+                },
+                '`' => {
+                    self.bump();
+                    word.parts.push(self.backquoted(false)?);
+                }
+                _ => {
+                    self.bump();
+                    word.push_char(c);
+                }
+            }
+        }
+
+        Ok(word)
+    }
+
+    // Parses the inside of a double-quoted string, or a whole here-document
+    // body, where double quotes have no special meaning.
+    fn quoted_parts(&mut self, heredoc: bool) -> Result<Vec<WordPart>, ParseError> {
+        let mut parts = Vec::new();
+
+        loop {
+            match self.bump() {
+                Some('"') if !heredoc => break,
+                Some('\\') => match self.peek() {
+                    Some('\n') => {
+                        self.bump();
+                    }
+                    Some('"') if !heredoc => {
+                        self.bump();
+                        parts.push(WordPart::Escaped('"'));
+                    }
+                    Some(c @ ('$' | '`' | '\\')) => {
+                        self.bump();
+                        parts.push(WordPart::Escaped(c));
+                    }
+                    Some(_) => push_literal(&mut parts, '\\'),
+                    None => return Err(ParseError::Incomplete),
+                },
+                Some('$') => {
+                    self.pos -= 1;
+                    match self.dollar()? {
+                        Some(part) => parts.push(part),
+                        None => {
+                            self.bump();
+                            push_literal(&mut parts, '$');
+                        }
+                    }
+                }
+                Some('`') => parts.push(self.backquoted(!heredoc)?),
+                Some(c) => push_literal(&mut parts, c),
+                None if heredoc => break,
+                None => return Err(ParseError::Incomplete),
+            }
+        }
+
+        Ok(parts)
+    }
+
+    fn name(&mut self) -> String {
+        let mut name = String::new();
+        while let Some(c) = self.peek() {
+            if !(c.is_ascii_alphanumeric() || c == '_') {
+                break;
+            }
+            name.push(c);
+            self.bump();
+        }
+        name
+    }
+
+    // Parses a `$` expansion at the current position, leaving the position
+    // untouched when the `$` does not start one.
+    fn dollar(&mut self) -> Result<Option<WordPart>, ParseError> {
+        let start = self.pos;
+        let param = match self.peek_at(1) {
+            Some('(') if self.peek_at(2) == Some('(') => {
+                self.pos += 3;
+                if let Some(parts) = self.arith_expression()? {
+                    return Ok(Some(WordPart::Arith(parts)));
+                }
+                // `$( (...) )` written without the space is a command.
+                self.pos = start + 2;
+                let command = self.parenthesized()?;
+                return Ok(Some(command_sub(command)?));
+            }
+            Some('(') => {
+                self.pos += 2;
+                let command = self.parenthesized()?;
+                return Ok(Some(command_sub(command)?));
+            }
+            Some('{') => {
+                self.pos += 2;
+                let content = self.braced()?;
+                braced_param(&content)?
+            }
+            Some(c)
+                if matches!(c, '?' | '$' | '#' | '@' | '*' | '!' | '-') || c.is_ascii_digit() =>
+            {
+                self.pos += 2;
+                Param {
+                    name: c.to_string(),
+                    index: None,
+                    op: ParamOp::Plain,
+                    source: String::new(),
+                }
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                self.bump();
+                Param {
+                    name: self.name(),
+                    index: None,
+                    op: ParamOp::Plain,
+                    source: String::new(),
+                }
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(WordPart::Param(Param {
+            source: self.chars[start..self.pos].iter().collect(),
+            ..param
+        })))
+    }
+
+    // Reads up to the `}` closing a `${`, skipping over quoted text and
+    // nested braces, and returns what is between them.
+    fn braced(&mut self) -> Result<String, ParseError> {
+        let start = self.pos;
+        let mut depth = 1;
+
+        loop {
+            match self.bump() {
+                Some('{') => depth += 1,
+                Some('}') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                Some('\\') => {
+                    self.bump();
+                }
+                Some(quote @ ('\'' | '"')) => loop {
+                    match self.bump() {
+                        Some(c) if c == quote => break,
+                        Some('\\') if quote == '"' => {
+                            self.bump();
+                        }
+                        Some(_) => {}
+                        None => return Err(ParseError::Incomplete),
+                    }
+                },
+                Some(_) => {}
+                None => return Err(ParseError::Incomplete),
+            }
+        }
+
+        Ok(self.chars[start..self.pos - 1].iter().collect())
+    }
+
+    // Reads up to the `)` closing a `$(` and returns what is between them.
+    // The text is tokenized as commands, and the closing `)` is the first one
+    // before which it parses completely; any other belongs to a case pattern
+    // or a subshell inside.
+    fn parenthesized(&mut self) -> Result<String, ParseError> {
+        let mut lexer = Lexer {
+            chars: self.chars[self.pos..].to_vec(),
+            pos: 0,
+            heredocs: Vec::new(),
+        };
+        let mut tokens = Vec::new();
+
+        while lexer.read_token(&mut tokens)? {
+            if tokens.last() != Some(&Token::RParen) {
+                continue;
+            }
+            let command: String = lexer.chars[..lexer.pos - 1].iter().collect();
+            match parser::parse(&command) {
+                Ok(_) => {
+                    self.pos += lexer.pos;
+                    return Ok(command);
+                }
+                Err(ParseError::Incomplete) => {}
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(ParseError::Incomplete)
+    }
+
+    // Reads an arithmetic expression after its opening `((`, up to the
+    // matching `))`. Returns `None`, with the position unspecified, when the
+    // parentheses close separately and so do not form one.
+    fn arith_expression(&mut self) -> Result<Option<Vec<WordPart>>, ParseError> {
+        let start = self.pos;
+        let mut depth = 0;
+
+        loop {
+            match self.bump() {
+                Some('(') => depth += 1,
+                Some(')') if depth > 0 => depth -= 1,
+                Some(')') if self.peek() == Some(')') => {
+                    self.bump();
+                    break;
+                }
+                Some(')') => return Ok(None),
+                Some('\\') => {
+                    self.bump();
+                }
+                Some(quote @ ('\'' | '"' | '`')) => loop {
+                    match self.bump() {
+                        Some(c) if c == quote => break,
+                        Some('\\') if quote != '\'' => {
+                            self.bump();
+                        }
+                        Some(_) => {}
+                        None => return Err(ParseError::Incomplete),
+                    }
+                },
+                Some(_) => {}
+                None => return Err(ParseError::Incomplete),
+            }
+        }
+
+        let text: String = self.chars[start..self.pos - 2].iter().collect();
+        Lexer::new(&text).operand().map(|word| Some(word.parts))
+    }
+
+    // Reads a backquoted command after its opening backquote. A backslash
+    // only quotes `$`, `` ` `` and `\` here, and also `"` when the command is
+    // inside double quotes.
+    fn backquoted(&mut self, in_double_quotes: bool) -> Result<WordPart, ParseError> {
+        let mut command = String::new();
+
+        loop {
+            match self.bump() {
+                Some('`') => break,
+                Some('\\') => match self.bump() {
+                    Some(c @ ('$' | '`' | '\\')) => command.push(c),
+                    Some('"') if in_double_quotes => command.push('"'),
+                    Some(c) => {
+                        command.push('\\');
+                        command.push(c);
+                    }
+                    None => return Err(ParseError::Incomplete),
+                },
+                Some(c) => command.push(c),
+                None => return Err(ParseError::Incomplete),
+            }
+        }
+
+        command_sub(command)
+    }
+
+    // Reads the rest of the input as the operand of a parameter expansion,
+    // where blanks and operators have no special meaning.
+    fn operand(&mut self) -> Result<Word, ParseError> {
+        let mut word = Word::default();
+
+        while let Some(c) = self.peek() {
+            match c {
+                '\'' => {
+                    self.bump();
+                    let mut quoted = String::new();
+                    loop {
+                        match self.bump() {
+                            Some('\'') => break,
+                            Some(c) => quoted.push(c),
+                            None => return Err(ParseError::Incomplete),
+                        }
+                    }
+                    word.parts.push(WordPart::SingleQuoted(quoted));
+                }
+                '"' => {
+                    self.bump();
+                    let parts = self.quoted_parts(false)?;
+                    word.parts.push(WordPart::DoubleQuoted(parts));
+                }
+                '\\' => {
+                    self.bump();
+                    match self.bump() {
+                        Some(c) => word.parts.push(WordPart::Escaped(c)),
+                        None => word.push_char('\\'),
+                    }
+                }
+                '$' => match self.dollar()? {
+                    Some(part) => word.parts.push(part),
+                    None => {
+                        self.bump();
+                        word.push_char('$');
+                    }
+                },
+                '`' => {
+                    self.bump();
+                    word.parts.push(self.backquoted(false)?);
+                }
+                _ => {
+                    self.bump();
+                    word.push_char(c);
+                }
+            }
+        }
+
+        Ok(word)
+    }
+}
+
+// Checks the syntax of a substituted command up front, so that errors in it
+// are reported when the line is read rather than when it runs.
+fn command_sub(command: String) -> Result<WordPart, ParseError> {
+    match parser::parse(&command) {
+        Ok(_) => Ok(WordPart::CommandSub(command)),
+        Err(ParseError::Incomplete) => Err(ParseError::Unexpected(")".to_string())),
+        Err(e) => Err(e),
+    }
+}
+
+fn operand(text: &str) -> Result<Word, ParseError> {
+    Lexer::new(text).operand()
+}
+
+// The byte offset of the first `/` that is not quoted or escaped.
+fn find_slash(text: &str) -> Option<usize> {
+    let mut quote = None;
+    let mut escaped = false;
+
+    for (i, c) in text.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match (c, quote) {
+            ('\\', Some('\'')) => {}
+            ('\\', _) => escaped = true,
+            ('\'' | '"', None) => quote = Some(c),
+            (c, Some(q)) if c == q => quote = None,
+            ('/', None) => return Some(i),
+            _ => {}
+        }
+    }
+
+    None
+}
+
+// Parses text the way a here-document body is, for prompt strings such as
+// PS4 that are expanded before use.
+pub fn prompt(text: &str) -> Result<Word, ParseError> {
+    let parts = Lexer::new(text).quoted_parts(true)?;
+    Ok(Word {
+        parts: vec![WordPart::DoubleQuoted(parts)],
+    })
+}
+
+pub fn is_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Splits the start of a braced expansion into the parameter name and
+// what follows it.
+fn param_name(content: &str) -> Option<(&str, &str)> {
+    let first = content.chars().next()?;
+    let end = if first.is_ascii_alphabetic() || first == '_' {
+        content
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+            .unwrap_or(content.len())
+    } else if first.is_ascii_digit() {
+        content
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(content.len())
+    } else if matches!(first, '?' | '$' | '#' | '@' | '*' | '!' | '-') {
+        1
+    } else {
+        return None;
+    };
+    Some(content.split_at(end))
+}
+
+fn braced_param(content: &str) -> Result<Param, ParseError> {
+    let bad = || ParseError::BadSubstitution(format!("${{{}}}", content));
+
+    let (length, body) = match content.strip_prefix('#') {
+        Some(body) if !body.is_empty() => (true, body),
+        _ => (false, content),
+    };
+
+    let (name, mut rest) = param_name(body).ok_or_else(bad)?;
+
+    let mut index = None;
+    if let Some(after) = rest.strip_prefix('[') {
+        let close = after.find(']').ok_or_else(bad)?;
+        index = Some(after[..close].to_string());
+        rest = &after[close + 1..];
+    }
+
+    let op = if length {
+        if !rest.is_empty() {
+            return Err(bad());
+        }
+        ParamOp::Length
+    } else {
+        param_op(rest).ok_or_else(bad)??
+    };
+
+    Ok(Param {
+        name: name.to_string(),
+        index,
+        op,
+        source: String::new(),
+    })
+}
+
+fn param_op(rest: &str) -> Option<Result<ParamOp, ParseError>> {
+    if rest.is_empty() {
+        return Some(Ok(ParamOp::Plain));
+    }
+
+    let (colon, body) = match rest.strip_prefix(':') {
+        Some(body) => (true, body),
+        None => (false, rest),
+    };
+    let mut chars = body.chars();
+    let op = chars.next();
+    let word = chars.as_str();
+
+    let op = match op {
+        Some('-') => operand(word).map(|word| ParamOp::Default(colon, word)),
+        Some('=') => operand(word).map(|word| ParamOp::Assign(colon, word)),
+        Some('?') => operand(word).map(|word| ParamOp::Error(colon, word)),
+        Some('+') => operand(word).map(|word| ParamOp::Alternative(colon, word)),
+        _ if colon => {
+            let (offset, length) = match body.split_once(':') {
+                Some((offset, length)) => (offset, Some(length.to_string())),
+                None => (body, None),
+            };
+            Ok(ParamOp::Substring(offset.to_string(), length))
+        }
+        Some('#') => match word.strip_prefix('#') {
+            Some(word) => operand(word).map(|word| ParamOp::RemovePrefix(true, word)),
+            None => operand(word).map(|word| ParamOp::RemovePrefix(false, word)),
+        },
+        Some('%') => match word.strip_prefix('%') {
+            Some(word) => operand(word).map(|word| ParamOp::RemoveSuffix(true, word)),
+            None => operand(word).map(|word| ParamOp::RemoveSuffix(false, word)),
+        },
+        Some('/') => {
+            let (mode, word) = match word.chars().next() {
+                Some('/') => (ReplaceMode::All, &word[1..]),
+                Some('#') => (ReplaceMode::Prefix, &word[1..]),
+                Some('%') => (ReplaceMode::Suffix, &word[1..]),
+                _ => (ReplaceMode::First, word),
+            };
+            let (pattern, replacement) = match find_slash(word) {
+                Some(slash) => (&word[..slash], &word[slash + 1..]),
+                None => (word, ""),
+            };
+            operand(pattern).and_then(|pattern| {
+                let replacement = operand(replacement)?;
+                Ok(ParamOp::Replace(mode, pattern, replacement))
+            })
+        }
+        _ => return None,
+    };
+
+    Some(op)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailing_backslash_newline_is_incomplete() {
+        assert_eq!(tokenize("echo a \\\n"), Err(ParseError::Incomplete));
+        assert_eq!(tokenize("echo a\\\n"), Err(ParseError::Incomplete));
+        assert_eq!(tokenize("echo a \\\nb\n").map(|tokens| tokens.len()), Ok(4));
+    }
+
+    #[test]
+    fn case_inside_command_substitution() {
+        let tokens = tokenize("echo $(case x in x) echo y;; esac) z\n").unwrap();
+        let command = "case x in x) echo y;; esac".to_string();
+        assert_eq!(tokens.len(), 4);
+        assert!(matches!(
+            &tokens[1],
+            Token::Word(word) if word.parts == [WordPart::CommandSub(command)]
+        ));
+    }
+
+    #[test]
+    fn parenthesis_in_comment_inside_command_substitution() {
+        let tokens = tokenize("echo $(echo y # a ) here\n)\n").unwrap();
+        assert_eq!(tokens.len(), 3);
+    }
+
+    fn words(src: &str) -> Vec<Vec<WordPart>> {
+        tokenize(src)
+            .unwrap()
+            .into_iter()
+            .filter_map(|token| match token {
+                Token::Word(word) => Some(word.parts),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quoting_is_kept_with_the_text() {
+        assert_eq!(
+            words("echo \"a|b\"")[1],
+            [WordPart::DoubleQuoted(vec![WordPart::Literal(
+                "a|b".to_string()
+            )])]
+        );
+        assert_eq!(
+            words("echo '>'")[1],
+            [WordPart::SingleQuoted(">".to_string())]
+        );
+        assert_eq!(
+            words("a\\ b"),
+            [vec![
+                WordPart::Literal("a".to_string()),
+                WordPart::Escaped(' '),
+                WordPart::Literal("b".to_string()),
+            ]]
+        );
+    }
+
+    #[test]
+    fn operators() {
+        let tokens = tokenize("a && b || c; d & e\n").unwrap();
+        let operators: Vec<&Token> = tokens
+            .iter()
+            .filter(|token| !matches!(token, Token::Word(_)))
+            .collect();
+        assert_eq!(
+            operators,
+            [
+                &Token::AndIf,
+                &Token::OrIf,
+                &Token::Semi,
+                &Token::Amp,
+                &Token::Newline
+            ]
+        );
+    }
+
+    #[test]
+    fn redirections() {
+        let tokens = tokenize("a 2>&1 >| out\n").unwrap();
+        assert_eq!(tokens[1], Token::Redirect(Some(2), RedirectOp::DupOut));
+        assert_eq!(tokens[3], Token::Redirect(None, RedirectOp::Clobber));
+
+        let tokens = tokenize("cat <<-EOF\n\tbody\n\tEOF\n").unwrap();
+        assert_eq!(
+            tokens[1],
+            Token::HereDoc(
+                None,
+                Word {
+                    parts: vec![WordPart::DoubleQuoted(vec![WordPart::Literal(
+                        "body\n".to_string()
+                    )])]
+                }
+            )
+        );
+    }
+}
@@ -1,10 +1,22 @@
 #[allow(unused_imports)]
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 
+mod arith;
+mod ast;
+mod brace;
 mod commands;
+mod expand;
+mod glob;
 mod input;
+mod jobs;
+mod lexer;
+mod parser;
+mod pattern;
+mod redirect;
+mod shell;
+mod signals;
 
-use input::input;
+use input::Source;
 use rustyline::completion::Completer;
 use rustyline::highlight::Highlighter;
 use rustyline::hint::Hinter;
@@ -12,7 +24,11 @@ use rustyline::history::DefaultHistory;
 use rustyline::validate::{ValidationContext, ValidationResult, Validator};
 use rustyline::Editor;
 use rustyline::Helper;
+use shell::{Flow, Options, Shell};
 use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+use std::process;
 
 pub struct ShellCompleter {
     commands: HashSet<String>,
@@ -60,10 +76,14 @@ impl Hinter for ShellCompleter {
 }
 
 fn main() {
-    let builtin = ["echo", "exit", "type", "pwd", "cd", "history"]
-        .iter()
-        .map(|s| s.to_string())
-        .collect::<Vec<String>>();
+    let builtin = [
+        "echo", "exit", "type", "pwd", "cd", "history", "export", "unset", "env", "shopt", "let",
+        "break", "continue", "return", "shift", "local", "source", ".", "alias", "unalias", "jobs",
+        "fg", "bg", "wait", "disown", "trap", "set",
+    ]
+    .iter()
+    .map(|s| s.to_string())
+    .collect::<Vec<String>>();
 
     let mut executables = input::get_executables();
     executables.extend(builtin.clone());
@@ -79,26 +99,182 @@ fn main() {
         .expect("Failed to create rustyline Editor");
     rl.set_helper(Some(completer));
 
-    let histfile = std::env::var("HISTFILE").ok();
-    
-    if let Some(path) = histfile {
-        rl.load_history(&path).ok();
+    let mut shell = Shell::new(rl, builtin);
+    let (mut source, startup) = invocation(&mut shell);
+    if shell.interactive {
+        signals::ignore(&mut shell, &signals::INTERACTIVE);
+        jobs::enable(&mut shell);
+    }
+    run_startup_files(&mut shell, &startup);
+
+    if shell.interactive {
+        if let Ok(path) = std::env::var("HISTFILE") {
+            shell.rl.load_history(&path).ok();
+        }
     }
 
     loop {
-        let input_lines = input::input(&mut rl);
+        shell.flow = Flow::Normal;
+        if shell.interactive {
+            jobs::notify(&mut shell);
+        }
+        match input::input(&mut shell, &mut source) {
+            Some(Ok(list)) => {
+                commands::run_list(&list, &mut shell);
+            }
+            Some(Err(e)) => {
+                eprintln!("{}", e);
+                shell.last_status = 2;
+                if !shell.interactive {
+                    break;
+                }
+            }
+            None => {
+                if shell.interactive {
+                    eprintln!("exit");
+                }
+                break;
+            }
+        }
+    }
 
-        if input_lines.is_empty() {
-            continue;
+    let status = shell.last_status;
+    commands::exit_shell(&mut shell, status)
+}
+
+// Which files to run before reading commands.
+#[derive(Default)]
+struct Startup {
+    login: bool,
+    norc: bool,
+    rcfile: Option<String>,
+}
+
+// A login shell runs the profile; any other interactive shell runs the rc
+// file. Missing files are skipped unless given with `--rcfile`.
+fn run_startup_files(shell: &mut Shell, startup: &Startup) {
+    let home = shell.get_var("HOME").unwrap_or_default().to_string();
+    let path = if startup.login {
+        format!("{}/.ours_profile", home)
+    } else if shell.interactive && !startup.norc {
+        match &startup.rcfile {
+            Some(rcfile) => rcfile.clone(),
+            None => format!("{}/.oursrc", home),
         }
+    } else {
+        return;
+    };
 
-        let (cmds, args) = input::split_inputs(input_lines);
+    match commands::source_file(shell, Path::new(&path)) {
+        Ok(_) => {}
+        Err(e) if e.kind() == io::ErrorKind::NotFound && startup.rcfile.is_none() => {}
+        Err(e) => eprintln!("{}: {}", path, redirect::describe_io_error(&e)),
+    }
+}
 
-        if cmds.len() == 1 {
-            let (arg, output_conf) = input::redirection(args[0].clone());
-            commands::command_handler(&cmds[0], &arg, &builtin, output_conf, &mut rl);
-        } else {
-            commands::run_pipeline(cmds, args, &builtin, &mut rl);
+// Reads the command line: options, then a script or the `-c` command, then
+// the positional parameters. Returns where commands are read from and which
+// startup files apply.
+fn invocation(shell: &mut Shell) -> (Source, Startup) {
+    let mut args = std::env::args().skip(1).peekable();
+    let mut command = false;
+    let mut stdin = false;
+    let mut interactive = false;
+    let mut startup = Startup {
+        login: shell.arg0.starts_with('-'),
+        ..Startup::default()
+    };
+
+    while let Some(arg) = args.next_if(|arg| arg.starts_with('-')) {
+        let flags = match arg.as_str() {
+            "--login" => "l",
+            "--norc" => {
+                startup.norc = true;
+                continue;
+            }
+            "--rcfile" => {
+                match args.next() {
+                    Some(rcfile) => startup.rcfile = Some(rcfile),
+                    None => {
+                        eprintln!("{}: --rcfile: option requires an argument", shell.arg0);
+                        process::exit(2);
+                    }
+                }
+                continue;
+            }
+            "--" | "-" => break,
+            _ if arg.starts_with("--") => {
+                eprintln!("{}: {}: invalid option", shell.arg0, arg);
+                process::exit(2);
+            }
+            _ => &arg[1..],
+        };
+
+        for flag in flags.chars() {
+            match flag {
+                'c' => command = true,
+                's' => stdin = true,
+                'i' => interactive = true,
+                'l' => startup.login = true,
+                'o' => {
+                    let name = args.next().unwrap_or_default();
+                    match shell.options.get_mut(&name) {
+                        Some(option) => *option = true,
+                        None => {
+                            eprintln!("{}: {}: invalid option name", shell.arg0, name);
+                            process::exit(2);
+                        }
+                    }
+                }
+                _ => match Options::flag(flag).and_then(|name| shell.options.get_mut(name)) {
+                    Some(option) => *option = true,
+                    None => {
+                        eprintln!("{}: -{}: invalid option", shell.arg0, flag);
+                        process::exit(2);
+                    }
+                },
+            }
         }
     }
+
+    let mut rest: Vec<String> = args.collect();
+    let source = if command {
+        if rest.is_empty() {
+            eprintln!("{}: -c: option requires an argument", shell.arg0);
+            process::exit(2);
+        }
+        let text = rest.remove(0);
+        if !rest.is_empty() {
+            shell.arg0 = rest.remove(0);
+        }
+        Source::Text(text, 0)
+    } else if stdin || rest.is_empty() {
+        if interactive || io::stdin().is_terminal() {
+            shell.interactive = true;
+            shell.shopts.expand_aliases = true;
+            Source::Prompt
+        } else {
+            Source::Stdin
+        }
+    } else {
+        let script = rest.remove(0);
+        match fs::read_to_string(&script) {
+            Ok(text) => {
+                shell.arg0 = script;
+                Source::Text(text, 0)
+            }
+            Err(e) => {
+                let message = redirect::describe_io_error(&e);
+                eprintln!("{}: {}: {}", shell.arg0, script, message);
+                let status = match e.kind() {
+                    io::ErrorKind::NotFound => 127,
+                    _ => 126,
+                };
+                process::exit(status);
+            }
+        }
+    };
+
+    shell.positional = rest;
+    (source, startup)
 }
@@ -0,0 +1,594 @@
+use crate::ast::{
+    AndOr, Assignment, CaseItem, Command, CompoundCommand, Connector, List, Pipeline, Redirect,
+    RedirectOp, SimpleCommand, Word, WordPart,
+};
+use crate::lexer::{self, ParseError, Token};
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// Splits `NAME=value` into an assignment, if the word has that form.
+fn assignment(word: &Word) -> Option<Assignment> {
+    let (first, rest) = match word.parts.split_first() {
+        Some((WordPart::Literal(first), rest)) => (first, rest),
+        _ => return None,
+    };
+    let (name, value) = first.split_once('=')?;
+    if !lexer::is_name(name) {
+        return None;
+    }
+
+    let mut parts = Vec::new();
+    if !value.is_empty() {
+        parts.push(WordPart::Literal(value.to_string()));
+    }
+    parts.extend_from_slice(rest);
+
+    Some(Assignment {
+        name: name.to_string(),
+        value: Word { parts },
+    })
+}
+
+// Reserved words that end a compound command or one of its parts, and so
+// cannot start a command.
+const CLOSING_WORDS: [&str; 8] = ["then", "elif", "else", "fi", "do", "done", "esac", "}"];
+
+fn is_reserved(token: Option<&Token>, words: &[&str]) -> bool {
+    match token {
+        Some(Token::Word(word)) => word.as_unquoted().is_some_and(|text| words.contains(&text)),
+        _ => false,
+    }
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token>,
+    pos: usize,
+    aliases: Option<&'a HashMap<String, String>>,
+    // The aliases being expanded, each with the end of the tokens its value
+    // was replaced by.
+    active: Vec<(String, usize)>,
+    // A word following an alias whose value ends in a blank, which is
+    // checked for aliases as well.
+    alias_next: Option<usize>,
+}
+
+pub fn parse(src: &str) -> Result<List, ParseError> {
+    parse_with_aliases(src, None)
+}
+
+pub fn parse_with_aliases(
+    src: &str,
+    aliases: Option<&HashMap<String, String>>,
+) -> Result<List, ParseError> {
+    let mut parser = Parser {
+        tokens: lexer::tokenize(src)?,
+        pos: 0,
+        aliases,
+        active: Vec::new(),
+        alias_next: None,
+    };
+    parser.list()
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        if token.is_some() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn unexpected(token: Option<&Token>) -> ParseError {
+        match token {
+            Some(token) => ParseError::Unexpected(token.describe()),
+            None => ParseError::Incomplete,
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while self.peek() == Some(&Token::Newline) {
+            self.pos += 1;
+        }
+    }
+
+    fn list(&mut self) -> Result<List, ParseError> {
+        let mut list = List::default();
+
+        loop {
+            self.skip_newlines();
+            if self.peek().is_none() {
+                break;
+            }
+            list.items.push(self.and_or()?);
+
+            match self.peek() {
+                None | Some(Token::Newline) => {}
+                Some(Token::Semi) => {
+                    self.next();
+                }
+                Some(Token::Amp) => {
+                    self.next();
+                    if let Some(and_or) = list.items.last_mut() {
+                        and_or.background = true;
+                    }
+                }
+                token => return Err(Self::unexpected(token)),
+            }
+        }
+
+        Ok(list)
+    }
+
+    fn and_or(&mut self) -> Result<AndOr, ParseError> {
+        let mut and_or = AndOr {
+            first: self.pipeline()?,
+            rest: Vec::new(),
+            background: false,
+        };
+
+        loop {
+            let connector = match self.peek() {
+                Some(Token::AndIf) => Connector::And,
+                Some(Token::OrIf) => Connector::Or,
+                _ => break,
+            };
+            self.next();
+            self.skip_newlines();
+            and_or.rest.push((connector, self.pipeline()?));
+        }
+
+        Ok(and_or)
+    }
+
+    fn pipeline(&mut self) -> Result<Pipeline, ParseError> {
+        let mut pipeline = Pipeline::default();
+        if is_reserved(self.peek(), &["!"]) {
+            self.next();
+            pipeline.negated = true;
+        }
+        pipeline.commands.push(self.command()?);
+
+        while self.peek() == Some(&Token::Pipe) {
+            self.next();
+            self.skip_newlines();
+            pipeline.commands.push(self.command()?);
+        }
+
+        Ok(pipeline)
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), ParseError> {
+        if self.peek() == Some(&expected) {
+            self.next();
+            Ok(())
+        } else {
+            Err(Self::unexpected(self.peek()))
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Result<(), ParseError> {
+        if is_reserved(self.peek(), &[word]) {
+            self.next();
+            Ok(())
+        } else {
+            Err(Self::unexpected(self.peek()))
+        }
+    }
+
+    // Parses the commands inside a compound command, up to one of the
+    // reserved words in `ends`, a `)` or a `;;`.
+    fn compound_list(&mut self, ends: &[&str]) -> Result<List, ParseError> {
+        let mut list = List::default();
+
+        loop {
+            self.skip_newlines();
+            match self.peek() {
+                None => return Err(ParseError::Incomplete),
+                Some(Token::RParen | Token::DSemi) => break,
+                token if is_reserved(token, ends) => break,
+                _ => {}
+            }
+            list.items.push(self.and_or()?);
+
+            match self.peek() {
+                Some(Token::Newline | Token::Semi) => {
+                    self.next();
+                }
+                Some(Token::Amp) => {
+                    self.next();
+                    if let Some(and_or) = list.items.last_mut() {
+                        and_or.background = true;
+                    }
+                }
+                None | Some(Token::RParen | Token::DSemi) => {}
+                token if is_reserved(token, ends) => {}
+                token => return Err(Self::unexpected(token)),
+            }
+        }
+
+        if list.items.is_empty() {
+            return Err(Self::unexpected(self.peek()));
+        }
+        Ok(list)
+    }
+
+    fn if_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        let mut branches = Vec::new();
+        let mut otherwise = None;
+
+        loop {
+            let condition = self.compound_list(&["then"])?;
+            self.expect_word("then")?;
+            let body = self.compound_list(&["elif", "else", "fi"])?;
+            branches.push((condition, body));
+
+            if is_reserved(self.peek(), &["elif"]) {
+                self.next();
+                continue;
+            }
+            if is_reserved(self.peek(), &["else"]) {
+                self.next();
+                otherwise = Some(self.compound_list(&["fi"])?);
+            }
+            self.expect_word("fi")?;
+            return Ok(CompoundCommand::If(branches, otherwise));
+        }
+    }
+
+    fn loop_body(&mut self) -> Result<List, ParseError> {
+        self.skip_newlines();
+        self.expect_word("do")?;
+        let body = self.compound_list(&["done"])?;
+        self.expect_word("done")?;
+        Ok(body)
+    }
+
+    fn for_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        if let Some(Token::Arith(_)) = self.peek() {
+            if let Some(Token::Arith(header)) = self.next() {
+                if self.peek() == Some(&Token::Semi) {
+                    self.next();
+                }
+                return Ok(CompoundCommand::ArithFor(header, self.loop_body()?));
+            }
+        }
+
+        let name = match self.next() {
+            Some(Token::Word(word)) => match word.as_unquoted() {
+                Some(name) if lexer::is_name(name) => name.to_string(),
+                _ => return Err(ParseError::Unexpected(word.text())),
+            },
+            token => return Err(Self::unexpected(token.as_ref())),
+        };
+
+        self.skip_newlines();
+        let words = if is_reserved(self.peek(), &["in"]) {
+            self.next();
+            let mut words = Vec::new();
+            while let Some(Token::Word(_)) = self.peek() {
+                if let Some(Token::Word(word)) = self.next() {
+                    words.push(word);
+                }
+            }
+            match self.next() {
+                Some(Token::Semi | Token::Newline) => {}
+                token => return Err(Self::unexpected(token.as_ref())),
+            }
+            Some(words)
+        } else {
+            if self.peek() == Some(&Token::Semi) {
+                self.next();
+            }
+            None
+        };
+
+        Ok(CompoundCommand::For(name, words, self.loop_body()?))
+    }
+
+    fn case_clause(&mut self) -> Result<CompoundCommand, ParseError> {
+        let word = match self.next() {
+            Some(Token::Word(word)) => word,
+            token => return Err(Self::unexpected(token.as_ref())),
+        };
+        self.skip_newlines();
+        self.expect_word("in")?;
+
+        let mut items = Vec::new();
+        loop {
+            self.skip_newlines();
+            if is_reserved(self.peek(), &["esac"]) {
+                self.next();
+                break;
+            }
+            if self.peek() == Some(&Token::LParen) {
+                self.next();
+            }
+
+            let mut patterns = Vec::new();
+            loop {
+                match self.next() {
+                    Some(Token::Word(pattern)) => patterns.push(pattern),
+                    token => return Err(Self::unexpected(token.as_ref())),
+                }
+                match self.next() {
+                    Some(Token::Pipe) => {}
+                    Some(Token::RParen) => break,
+                    token => return Err(Self::unexpected(token.as_ref())),
+                }
+            }
+
+            self.skip_newlines();
+            let body = if self.peek() == Some(&Token::DSemi) || is_reserved(self.peek(), &["esac"])
+            {
+                List::default()
+            } else {
+                self.compound_list(&["esac"])?
+            };
+            items.push(CaseItem { patterns, body });
+
+            match self.peek() {
+                Some(Token::DSemi) => {
+                    self.next();
+                }
+                token if is_reserved(token, &["esac"]) => {}
+                token => return Err(Self::unexpected(token)),
+            }
+        }
+
+        Ok(CompoundCommand::Case(word, items))
+    }
+
+    // Parses a compound command if one starts here.
+    fn compound(&mut self) -> Result<Option<CompoundCommand>, ParseError> {
+        if self.peek() == Some(&Token::LParen) {
+            self.next();
+            let list = self.compound_list(&[])?;
+            self.expect(Token::RParen)?;
+            return Ok(Some(CompoundCommand::Subshell(list)));
+        }
+
+        let keyword = match self.peek() {
+            Some(Token::Word(word)) => word.as_unquoted().unwrap_or("").to_string(),
+            _ => return Ok(None),
+        };
+
+        let compound = match keyword.as_str() {
+            "if" | "while" | "until" | "for" | "case" | "{" => {
+                self.next();
+                match keyword.as_str() {
+                    "if" => self.if_clause()?,
+                    "while" | "until" => {
+                        let condition = self.compound_list(&["do"])?;
+                        CompoundCommand::While(keyword == "until", condition, self.loop_body()?)
+                    }
+                    "for" => self.for_clause()?,
+                    "case" => self.case_clause()?,
+                    _ => {
+                        let list = self.compound_list(&["}"])?;
+                        self.expect_word("}")?;
+                        CompoundCommand::Group(list)
+                    }
+                }
+            }
+            word if CLOSING_WORDS.contains(&word) => {
+                return Err(ParseError::Unexpected(keyword));
+            }
+            _ => return Ok(None),
+        };
+
+        Ok(Some(compound))
+    }
+
+    // A compound command along with any redirections that follow it.
+    fn compound_command(&mut self) -> Result<Option<Command>, ParseError> {
+        let compound = match self.compound()? {
+            Some(compound) => compound,
+            None => return Ok(None),
+        };
+
+        let mut redirects = Vec::new();
+        while let Some(Token::Redirect(..) | Token::HereDoc(..)) = self.peek() {
+            redirects.push(self.redirect()?);
+        }
+        Ok(Some(Command::Compound(compound, redirects)))
+    }
+
+    fn function_body(&mut self, name: String) -> Result<Command, ParseError> {
+        self.skip_newlines();
+        match self.compound_command()? {
+            Some(body) => Ok(Command::Function(name, Rc::new(body))),
+            None => Err(Self::unexpected(self.peek())),
+        }
+    }
+
+    // Replaces an alias at the current position by the tokens of its value,
+    // for as long as the first word is an alias that isn't already being
+    // expanded.
+    fn expand_aliases(&mut self) -> Result<(), ParseError> {
+        let aliases = match self.aliases {
+            Some(aliases) => aliases,
+            None => return Ok(()),
+        };
+        let start = self.pos;
+        let mut end = start + 1;
+        let mut blank = None;
+
+        loop {
+            self.active.retain(|(_, region_end)| *region_end > start);
+            let name = match self.peek() {
+                Some(Token::Word(word)) => word.as_unquoted(),
+                _ => None,
+            };
+            let (name, value) = match name.and_then(|name| aliases.get_key_value(name)) {
+                Some((name, _)) if self.active.iter().any(|(active, _)| active == name) => break,
+                Some(alias) => alias,
+                None => break,
+            };
+
+            let tokens = lexer::tokenize(value)?;
+            let count = tokens.len();
+            // The outermost value decides whether the next word is checked
+            // too: an alias nested in it only ends the text it replaced.
+            blank.get_or_insert(value.ends_with([' ', '\t']));
+            self.tokens.splice(start..start + 1, tokens);
+            for (_, region_end) in &mut self.active {
+                *region_end = *region_end + count - 1;
+            }
+            end = end + count - 1;
+            self.active.push((name.clone(), start + count));
+            if count == 0 {
+                break;
+            }
+        }
+
+        if blank == Some(true) {
+            self.alias_next = Some(end);
+        }
+        Ok(())
+    }
+
+    fn command(&mut self) -> Result<Command, ParseError> {
+        self.expand_aliases()?;
+
+        if let Some(Token::Arith(_)) = self.peek() {
+            if let Some(Token::Arith(word)) = self.next() {
+                return Ok(Command::Arith(word));
+            }
+        }
+
+        if is_reserved(self.peek(), &["function"]) {
+            self.next();
+            let name = match self.next() {
+                Some(Token::Word(word)) => word.text(),
+                token => return Err(Self::unexpected(token.as_ref())),
+            };
+            if self.peek() == Some(&Token::LParen) {
+                self.next();
+                self.expect(Token::RParen)?;
+            }
+            return self.function_body(name);
+        }
+
+        if let Some(Token::Word(word)) = self.peek() {
+            let name = word.as_unquoted().map(str::to_string);
+            if let (Some(name), Some(Token::LParen)) = (name, self.tokens.get(self.pos + 1)) {
+                self.pos += 2;
+                self.expect(Token::RParen)?;
+                return self.function_body(name);
+            }
+        }
+
+        if let Some(command) = self.compound_command()? {
+            return Ok(command);
+        }
+
+        let mut simple = SimpleCommand::default();
+
+        loop {
+            if simple.words.is_empty() || self.alias_next == Some(self.pos) {
+                self.expand_aliases()?;
+            }
+            match self.peek() {
+                Some(Token::Word(_)) => {
+                    if let Some(Token::Word(word)) = self.next() {
+                        match assignment(&word) {
+                            Some(assignment) if simple.words.is_empty() => {
+                                simple.assignments.push(assignment)
+                            }
+                            _ => simple.words.push(word),
+                        }
+                    }
+                }
+                Some(Token::Redirect(..) | Token::HereDoc(..)) => {
+                    simple.redirects.push(self.redirect()?);
+                }
+                _ => break,
+            }
+        }
+
+        if simple.assignments.is_empty() && simple.words.is_empty() && simple.redirects.is_empty() {
+            return Err(Self::unexpected(self.peek()));
+        }
+
+        Ok(Command::Simple(simple))
+    }
+
+    fn redirect(&mut self) -> Result<Redirect, ParseError> {
+        let (fd, op) = match self.next() {
+            Some(Token::Redirect(fd, op)) => (fd, op),
+            Some(Token::HereDoc(fd, body)) => {
+                return Ok(Redirect {
+                    fd,
+                    op: RedirectOp::HereDoc,
+                    target: body,
+                })
+            }
+            token => return Err(Self::unexpected(token.as_ref())),
+        };
+
+        match self.next() {
+            Some(Token::Word(target)) => Ok(Redirect { fd, op, target }),
+            token => Err(Self::unexpected(token.as_ref())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn simple(command: &Command) -> &SimpleCommand {
+        match command {
+            Command::Simple(simple) => simple,
+            command => panic!("not a simple command: {:?}", command),
+        }
+    }
+
+    #[test]
+    fn lists_and_connectors() {
+        let list = parse("a && b || c; d & e\n").unwrap();
+        assert_eq!(list.items.len(), 3);
+
+        let first = &list.items[0];
+        let connectors: Vec<Connector> =
+            first.rest.iter().map(|(connector, _)| *connector).collect();
+        assert_eq!(connectors, [Connector::And, Connector::Or]);
+        assert!(!first.background);
+        assert!(list.items[1].background);
+        assert!(!list.items[2].background);
+    }
+
+    #[test]
+    fn quoted_operators_are_words() {
+        let list = parse("echo \"a|b\" '>' a\\ b\n").unwrap();
+        let command = simple(&list.items[0].first.commands[0]);
+        let words: Vec<String> = command.words.iter().map(Word::text).collect();
+        assert_eq!(words, ["echo", "a|b", ">", "a b"]);
+        assert!(command.redirects.is_empty());
+    }
+
+    #[test]
+    fn redirections() {
+        let list = parse("a 2>&1 >| out <<-EOF\n\tbody\n\tEOF\n").unwrap();
+        let command = simple(&list.items[0].first.commands[0]);
+        let redirects: Vec<(Option<i32>, RedirectOp, String)> = command
+            .redirects
+            .iter()
+            .map(|redirect| (redirect.fd, redirect.op, redirect.target.text()))
+            .collect();
+        assert_eq!(
+            redirects,
+            [
+                (Some(2), RedirectOp::DupOut, "1".to_string()),
+                (None, RedirectOp::Clobber, "out".to_string()),
+                (None, RedirectOp::HereDoc, "body\n".to_string()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,207 @@
+// Shell pattern matching, shared by parameter expansion, `case` and
+// pathname expansion. Patterns are written in glob syntax; a backslash makes
+// the following character literal, which is how quoted text is passed in.
+
+#[derive(Debug, Clone, PartialEq)]
+enum ClassItem {
+    Char(char),
+    Range(char, char),
+    Named(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Char(char),
+    Any,
+    Star,
+    Class(bool, Vec<ClassItem>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pattern {
+    tokens: Vec<Token>,
+}
+
+// Escapes `text` so that it matches only itself.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        if matches!(c, '*' | '?' | '[' | ']' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn named_class(name: &str, c: char) -> bool {
+    match name {
+        "alnum" => c.is_alphanumeric(),
+        "alpha" => c.is_alphabetic(),
+        "blank" => c == ' ' || c == '\t',
+        "cntrl" => c.is_control(),
+        "digit" => c.is_ascii_digit(),
+        "graph" => !c.is_whitespace() && !c.is_control(),
+        "lower" => c.is_lowercase(),
+        "print" => !c.is_control(),
+        "punct" => c.is_ascii_punctuation(),
+        "space" => c.is_whitespace(),
+        "upper" => c.is_uppercase(),
+        "xdigit" => c.is_ascii_hexdigit(),
+        _ => false,
+    }
+}
+
+// Parses a bracket expression starting just after the `[`. Returns the
+// token and the number of characters consumed, or `None` when the bracket
+// is not closed and so stands for itself.
+fn parse_class(chars: &[char]) -> Option<(Token, usize)> {
+    let mut i = 0;
+    let negated = matches!(chars.first(), Some('!' | '^'));
+    if negated {
+        i += 1;
+    }
+
+    let mut items = Vec::new();
+    let mut first = true;
+
+    loop {
+        let c = *chars.get(i)?;
+        if c == ']' && !first {
+            return Some((Token::Class(negated, items), i + 1));
+        }
+        first = false;
+
+        if c == '[' && chars.get(i + 1) == Some(&':') {
+            let rest: String = chars[i + 2..].iter().collect();
+            if let Some(end) = rest.find(":]") {
+                let name = &rest[..end];
+                items.push(ClassItem::Named(name.to_string()));
+                i += 2 + name.chars().count() + 2;
+                continue;
+            }
+        }
+
+        let c = if c == '\\' {
+            i += 1;
+            *chars.get(i)?
+        } else {
+            c
+        };
+
+        if chars.get(i + 1) == Some(&'-') && chars.get(i + 2).is_some_and(|&end| end != ']') {
+            let mut end = chars[i + 2];
+            let mut width = 3;
+            if end == '\\' {
+                end = *chars.get(i + 3)?;
+                width = 4;
+            }
+            items.push(ClassItem::Range(c, end));
+            i += width;
+        } else {
+            items.push(ClassItem::Char(c));
+            i += 1;
+        }
+    }
+}
+
+impl Token {
+    fn matches(&self, c: char) -> bool {
+        match self {
+            Token::Char(expected) => *expected == c,
+            Token::Any => true,
+            Token::Star => false,
+            Token::Class(negated, items) => {
+                let found = items.iter().any(|item| match item {
+                    ClassItem::Char(expected) => *expected == c,
+                    ClassItem::Range(start, end) => *start <= c && c <= *end,
+                    ClassItem::Named(name) => named_class(name, c),
+                });
+                found != *negated
+            }
+        }
+    }
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Pattern {
+        let chars: Vec<char> = source.chars().collect();
+        let mut tokens = Vec::new();
+        let mut i = 0;
+
+        while i < chars.len() {
+            match chars[i] {
+                '\\' if i + 1 < chars.len() => {
+                    tokens.push(Token::Char(chars[i + 1]));
+                    i += 2;
+                }
+                '*' => {
+                    if tokens.last() != Some(&Token::Star) {
+                        tokens.push(Token::Star);
+                    }
+                    i += 1;
+                }
+                '?' => {
+                    tokens.push(Token::Any);
+                    i += 1;
+                }
+                '[' => match parse_class(&chars[i + 1..]) {
+                    Some((token, width)) => {
+                        tokens.push(token);
+                        i += 1 + width;
+                    }
+                    None => {
+                        tokens.push(Token::Char('['));
+                        i += 1;
+                    }
+                },
+                c => {
+                    tokens.push(Token::Char(c));
+                    i += 1;
+                }
+            }
+        }
+
+        Pattern { tokens }
+    }
+
+    pub fn matches(&self, text: &str) -> bool {
+        let chars: Vec<char> = text.chars().collect();
+        self.matches_chars(&chars)
+    }
+
+    // The text the pattern stands for when it has no special characters.
+    pub fn literal(&self) -> Option<String> {
+        self.tokens
+            .iter()
+            .map(|token| match token {
+                Token::Char(c) => Some(*c),
+                _ => None,
+            })
+            .collect()
+    }
+
+    pub fn matches_chars(&self, text: &[char]) -> bool {
+        let tokens = &self.tokens;
+        let (mut t, mut p) = (0, 0);
+        let mut backtrack: Option<(usize, usize)> = None;
+
+        while t < text.len() {
+            if p < tokens.len() && tokens[p] == Token::Star {
+                backtrack = Some((p, t));
+                p += 1;
+            } else if p < tokens.len() && tokens[p].matches(text[t]) {
+                p += 1;
+                t += 1;
+            } else if let Some((star, start)) = backtrack {
+                p = star + 1;
+                t = start + 1;
+                backtrack = Some((star, start + 1));
+            } else {
+                return false;
+            }
+        }
+
+        tokens[p..].iter().all(|token| *token == Token::Star)
+    }
+}
@@ -0,0 +1,283 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::os::unix::process::CommandExt;
+use std::process::{self, Command, Stdio};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use nix::errno::Errno;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::unistd::{close, dup2, pipe2};
+
+use crate::input::{FdOp, OpenMode};
+
+// Descriptors handed to children beyond stdio are first moved at or above
+// this number so that placing them cannot clobber one another.
+pub const HIGH_FD: RawFd = 10;
+
+// The file descriptors of a command. A descriptor that is not in the table
+// is inherited from the shell; `None` marks one that has been closed.
+#[derive(Default)]
+pub struct Streams {
+    fds: BTreeMap<RawFd, Option<Rc<File>>>,
+}
+
+// The shell's own descriptors replaced by `Streams::install`.
+pub struct Saved {
+    fds: Vec<(RawFd, Option<OwnedFd>)>,
+}
+
+impl Saved {
+    pub fn restore(self) {
+        io::stdout().flush().ok();
+        for (fd, copy) in self.fds.into_iter().rev() {
+            match copy {
+                Some(copy) => {
+                    dup2(copy.as_raw_fd(), fd).ok();
+                }
+                None => {
+                    close(fd).ok();
+                }
+            }
+        }
+    }
+}
+
+pub fn pipe() -> io::Result<(File, File)> {
+    let (read, write) = pipe2(OFlag::O_CLOEXEC)?;
+    unsafe { Ok((File::from_raw_fd(read), File::from_raw_fd(write))) }
+}
+
+// Closes the descriptors that would not survive an exec, which is what a
+// forked copy of the shell holds for other processes, such as the read end
+// of the pipe into the next stage of a pipeline. Whatever owns them must not
+// be used afterwards.
+pub fn close_private() {
+    let fds: Vec<RawFd> = match fs::read_dir("/proc/self/fd") {
+        Ok(entries) => entries
+            .flatten()
+            .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+            .collect(),
+        Err(_) => (3..1024).collect(),
+    };
+    for fd in fds.into_iter().filter(|&fd| fd > 2) {
+        let cloexec = fcntl(fd, FcntlArg::F_GETFD)
+            .is_ok_and(|flags| FdFlag::from_bits_truncate(flags).contains(FdFlag::FD_CLOEXEC));
+        if cloexec {
+            close(fd).ok();
+        }
+    }
+}
+
+// The system's description of an error, without the "(os error N)" suffix.
+pub fn describe_io_error(error: &io::Error) -> String {
+    let message = error.to_string();
+    match message.find(" (os error") {
+        Some(end) => message[..end].to_string(),
+        None => message,
+    }
+}
+
+// Under noclobber a new file is created, but an existing regular file is
+// not overwritten. Anything else, such as /dev/null, is still written to.
+fn open_new(path: &str) -> io::Result<File> {
+    match OpenOptions::new().write(true).create_new(true).open(path) {
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+            let file = OpenOptions::new().write(true).open(path)?;
+            if file.metadata()?.is_file() {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    "cannot overwrite existing file",
+                ));
+            }
+            Ok(file)
+        }
+        result => result,
+    }
+}
+
+fn open(path: &str, mode: &OpenMode) -> io::Result<File> {
+    let mut options = OpenOptions::new();
+    match mode {
+        OpenMode::Read => options.read(true),
+        OpenMode::Write => options.write(true).create(true).truncate(true),
+        OpenMode::WriteNew => return open_new(path),
+        OpenMode::Append => options.append(true).create(true),
+        OpenMode::ReadWrite => options.read(true).write(true).create(true),
+    };
+    options.open(path)
+}
+
+static HEREDOC_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+// Here-documents and here-strings are stored in an unlinked temporary file,
+// so any amount of text can be read back without a writer on the other end.
+fn here_document(text: &str) -> io::Result<File> {
+    let path = env::temp_dir().join(format!(
+        "shell-heredoc-{}-{}",
+        process::id(),
+        HEREDOC_COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    fs::remove_file(&path)?;
+
+    file.write_all(text.as_bytes())?;
+    file.rewind()?;
+    Ok(file)
+}
+
+// A descriptor of the shell itself, duplicated so that it can be owned.
+fn inherited(fd: RawFd) -> io::Result<File> {
+    let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?;
+    unsafe { Ok(File::from_raw_fd(copy)) }
+}
+
+impl Streams {
+    pub fn set(&mut self, fd: RawFd, file: File) {
+        self.fds.insert(fd, Some(Rc::new(file)));
+    }
+
+    fn file(&self, fd: RawFd) -> io::Result<File> {
+        match self.fds.get(&fd) {
+            Some(Some(file)) => file.try_clone(),
+            Some(None) => Err(io::Error::from_raw_os_error(Errno::EBADF as i32)),
+            None => inherited(fd),
+        }
+    }
+
+    // Applies redirection steps in order. On failure the error message is
+    // returned ready to be printed.
+    pub fn apply(&mut self, ops: &[FdOp]) -> Result<(), String> {
+        for op in ops {
+            match op {
+                FdOp::Open(fd, path, mode) => match open(path, mode) {
+                    Ok(file) => self.set(*fd, file),
+                    Err(e) => return Err(format!("{}: {}", path, describe_io_error(&e))),
+                },
+                FdOp::Text(fd, text) => match here_document(text) {
+                    Ok(file) => self.set(*fd, file),
+                    Err(e) => return Err(format!("here-document: {}", describe_io_error(&e))),
+                },
+                FdOp::Dup(fd, source) => match self.file(*source) {
+                    Ok(file) => self.set(*fd, file),
+                    Err(e) => return Err(format!("{}: {}", source, describe_io_error(&e))),
+                },
+                FdOp::Close(fd) => {
+                    self.fds.insert(*fd, None);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn stdio(&self, fd: RawFd) -> Stdio {
+        match self.fds.get(&fd) {
+            Some(Some(file)) => match file.try_clone() {
+                Ok(file) => Stdio::from(file),
+                Err(_) => Stdio::null(),
+            },
+            _ => Stdio::inherit(),
+        }
+    }
+
+    // Wires the table into a child process: the standard streams through
+    // `Stdio`, any other descriptor by duplicating it onto its number right
+    // before exec.
+    pub fn configure(&self, command: &mut Command) {
+        command
+            .stdin(self.stdio(0))
+            .stdout(self.stdio(1))
+            .stderr(self.stdio(2));
+
+        let mut moves: Vec<(OwnedFd, RawFd)> = Vec::new();
+        let mut closes: Vec<RawFd> = Vec::new();
+
+        for (&fd, file) in &self.fds {
+            match file {
+                Some(_) if fd <= 2 => {}
+                Some(file) => {
+                    if let Ok(high) = fcntl(file.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(HIGH_FD)) {
+                        moves.push((unsafe { OwnedFd::from_raw_fd(high) }, fd));
+                    }
+                }
+                None => closes.push(fd),
+            }
+        }
+
+        if moves.is_empty() && closes.is_empty() {
+            return;
+        }
+
+        unsafe {
+            command.pre_exec(move || {
+                for (file, fd) in &moves {
+                    dup2(file.as_raw_fd(), *fd)?;
+                }
+                for fd in &closes {
+                    close(*fd).ok();
+                }
+                Ok(())
+            });
+        }
+    }
+
+    // Makes the table the shell's own descriptors, for running a compound
+    // command inside the shell. What was there before is kept so that it
+    // can be restored.
+    pub fn install(&self) -> Saved {
+        io::stdout().flush().ok();
+
+        let mut moves: Vec<(Option<OwnedFd>, RawFd)> = Vec::new();
+        for (&fd, file) in &self.fds {
+            let high = file.as_ref().and_then(|file| {
+                let high = fcntl(file.as_raw_fd(), FcntlArg::F_DUPFD_CLOEXEC(HIGH_FD)).ok()?;
+                Some(unsafe { OwnedFd::from_raw_fd(high) })
+            });
+            moves.push((high, fd));
+        }
+
+        let mut saved = Vec::new();
+        for (file, fd) in moves {
+            let copy = fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(HIGH_FD))
+                .ok()
+                .map(|copy| unsafe { OwnedFd::from_raw_fd(copy) });
+            saved.push((fd, copy));
+
+            match file {
+                Some(file) => {
+                    dup2(file.as_raw_fd(), fd).ok();
+                }
+                None => {
+                    close(fd).ok();
+                }
+            }
+        }
+
+        Saved { fds: saved }
+    }
+
+    // A writer for one of the command's descriptors, for use by builtins.
+    pub fn writer(&self, fd: RawFd) -> Box<dyn Write> {
+        match self.fds.get(&fd) {
+            Some(Some(file)) => match file.try_clone() {
+                Ok(file) => Box::new(file),
+                Err(_) => Box::new(io::sink()),
+            },
+            Some(None) => Box::new(io::sink()),
+            None if fd == 1 => Box::new(io::stdout()),
+            None if fd == 2 => Box::new(io::stderr()),
+            None => match inherited(fd) {
+                Ok(file) => Box::new(file),
+                Err(_) => Box::new(io::sink()),
+            },
+        }
+    }
+}
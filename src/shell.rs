@@ -0,0 +1,327 @@
+use std::collections::{BTreeMap, HashMap};
+use std::env;
+use std::os::fd::RawFd;
+use std::rc::Rc;
+
+use crate::ast::{Command, List};
+use crate::jobs::{Group, Job};
+use crate::lexer::ParseError;
+use crate::parser;
+use crate::signals::Trap;
+use crate::ShellCompleter;
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+
+// A shell variable. An exported variable is passed to the environment of
+// commands; it may be exported before it has a value.
+#[derive(Clone)]
+pub struct Var {
+    pub value: Option<String>,
+    pub exported: bool,
+}
+
+// Options changed with `shopt`.
+#[derive(Default)]
+pub struct Shopts {
+    pub dotglob: bool,
+    pub expand_aliases: bool,
+    pub failglob: bool,
+    pub globstar: bool,
+    pub nullglob: bool,
+}
+
+impl Shopts {
+    pub const NAMES: [&'static str; 5] = [
+        "dotglob",
+        "expand_aliases",
+        "failglob",
+        "globstar",
+        "nullglob",
+    ];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "dotglob" => Some(&mut self.dotglob),
+            "expand_aliases" => Some(&mut self.expand_aliases),
+            "failglob" => Some(&mut self.failglob),
+            "globstar" => Some(&mut self.globstar),
+            "nullglob" => Some(&mut self.nullglob),
+            _ => None,
+        }
+    }
+}
+
+// Options changed with `set`, or given on the command line.
+#[derive(Default)]
+pub struct Options {
+    pub errexit: bool,
+    pub noclobber: bool,
+    pub noglob: bool,
+    pub nounset: bool,
+    pub pipefail: bool,
+    pub xtrace: bool,
+}
+
+impl Options {
+    pub const NAMES: [&'static str; 6] = [
+        "errexit",
+        "noclobber",
+        "noglob",
+        "nounset",
+        "pipefail",
+        "xtrace",
+    ];
+
+    // The single-letter forms, in the order `$-` lists them.
+    pub const FLAGS: [(char, &'static str); 5] = [
+        ('e', "errexit"),
+        ('f', "noglob"),
+        ('u', "nounset"),
+        ('x', "xtrace"),
+        ('C', "noclobber"),
+    ];
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut bool> {
+        match name {
+            "errexit" => Some(&mut self.errexit),
+            "noclobber" => Some(&mut self.noclobber),
+            "noglob" => Some(&mut self.noglob),
+            "nounset" => Some(&mut self.nounset),
+            "pipefail" => Some(&mut self.pipefail),
+            "xtrace" => Some(&mut self.xtrace),
+            _ => None,
+        }
+    }
+
+    pub fn get(&self, name: &str) -> bool {
+        match name {
+            "errexit" => self.errexit,
+            "noclobber" => self.noclobber,
+            "noglob" => self.noglob,
+            "nounset" => self.nounset,
+            "pipefail" => self.pipefail,
+            "xtrace" => self.xtrace,
+            _ => false,
+        }
+    }
+
+    pub fn flag(flag: char) -> Option<&'static str> {
+        Self::FLAGS
+            .iter()
+            .find(|(c, _)| *c == flag)
+            .map(|(_, name)| *name)
+    }
+}
+
+// Set by `break` and `continue` to unwind to the enclosing loops, counting
+// how many levels remain, by `return`, and when a foreground job of an
+// interactive shell is interrupted, which abandons the whole command line.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flow {
+    Normal,
+    Break(usize),
+    Continue(usize),
+    Return,
+    Interrupt,
+}
+
+pub struct Shell {
+    pub rl: Editor<ShellCompleter, DefaultHistory>,
+    pub builtin: Vec<String>,
+    pub last_status: i32,
+    pub pipestatus: Vec<i32>,
+    pub vars: HashMap<String, Var>,
+    pub shopts: Shopts,
+    pub options: Options,
+    // Whether commands are read from a terminal with prompts and history.
+    pub interactive: bool,
+    // How many conditions are being tested, where a failing command must
+    // not end the shell under `-e`.
+    pub conditional: usize,
+    pub flow: Flow,
+    pub loop_depth: usize,
+    pub functions: HashMap<String, Rc<Command>>,
+    pub aliases: HashMap<String, String>,
+    // `$0` and the positional parameters.
+    pub arg0: String,
+    pub positional: Vec<String>,
+    // For each function call in progress, the outside values of the
+    // variables it made local.
+    pub frames: Vec<HashMap<String, Option<Var>>>,
+    // How many files are being run by `source`.
+    pub sourcing: usize,
+    // Set in a forked copy of the shell, which must leave the history alone.
+    pub subshell: bool,
+    // Background jobs and stopped ones.
+    pub jobs: Vec<Job>,
+    // In a forked copy of the shell, the jobs of its parent, which `jobs`
+    // still lists until the copy starts one of its own, as in `jobs | cat`.
+    pub parent_jobs: Option<Vec<Job>>,
+    // Counts job starts and stops, to order the jobs.
+    pub job_clock: u64,
+    pub job_control: bool,
+    // A copy of the terminal's descriptor while job control is on.
+    pub terminal: RawFd,
+    // The process group for the processes of the job being started.
+    pub group: Option<Group>,
+    // For `$$`, which stays the same in forked copies of the shell.
+    pub pid: Pid,
+    // The process started by the last `&`, for `$!`.
+    pub last_background: Option<Pid>,
+    // The status of the last command substitution in the current command.
+    pub substitution_status: Option<i32>,
+    // Signals the shell ignores for itself but not for the commands it runs.
+    pub ignored: Vec<Signal>,
+    pub traps: BTreeMap<Trap, String>,
+    // In a forked copy of the shell, the traps of its parent, which `trap`
+    // still lists until one is changed, as in `trap | grep`.
+    pub parent_traps: Option<BTreeMap<Trap, String>>,
+    // Set while a trap's action runs, which no other trap interrupts.
+    pub running_trap: bool,
+    // Whether the ERR trap has run for the failure of the command in
+    // progress, so that the commands around it don't run it again.
+    pub err_trapped: bool,
+}
+
+impl Shell {
+    pub fn new(rl: Editor<ShellCompleter, DefaultHistory>, builtin: Vec<String>) -> Shell {
+        let vars = env::vars()
+            .map(|(name, value)| {
+                let var = Var {
+                    value: Some(value),
+                    exported: true,
+                };
+                (name, var)
+            })
+            .collect();
+
+        Shell {
+            rl,
+            builtin,
+            last_status: 0,
+            pipestatus: vec![0],
+            vars,
+            shopts: Shopts::default(),
+            options: Options::default(),
+            interactive: false,
+            conditional: 0,
+            flow: Flow::Normal,
+            loop_depth: 0,
+            functions: HashMap::new(),
+            aliases: HashMap::new(),
+            arg0: env::args().next().unwrap_or_default(),
+            positional: Vec::new(),
+            frames: Vec::new(),
+            sourcing: 0,
+            subshell: false,
+            substitution_status: None,
+            jobs: Vec::new(),
+            parent_jobs: None,
+            job_clock: 0,
+            job_control: false,
+            terminal: -1,
+            group: None,
+            pid: Pid::this(),
+            last_background: None,
+            ignored: Vec::new(),
+            traps: BTreeMap::new(),
+            parent_traps: None,
+            running_trap: false,
+            err_trapped: false,
+        }
+    }
+
+    // Parses commands, expanding aliases if that is turned on.
+    pub fn parse(&self, src: &str) -> Result<List, ParseError> {
+        let aliases = self.shopts.expand_aliases.then_some(&self.aliases);
+        parser::parse_with_aliases(src, aliases)
+    }
+
+    // Records the statuses of a pipeline. With pipefail its status is that
+    // of the last stage to fail.
+    pub fn set_statuses(&mut self, statuses: Vec<i32>) {
+        self.last_status = match self.options.pipefail {
+            true => statuses.iter().rev().find(|&&status| status != 0),
+            false => statuses.last(),
+        }
+        .copied()
+        .unwrap_or(0);
+        self.pipestatus = statuses;
+    }
+
+    // The value of `$-`.
+    pub fn flags(&self) -> String {
+        let mut flags: String = Options::FLAGS
+            .iter()
+            .filter(|(_, name)| self.options.get(name))
+            .map(|(flag, _)| *flag)
+            .collect();
+        if self.interactive {
+            flags.push('i');
+        }
+        flags
+    }
+
+    pub fn get_var(&self, name: &str) -> Option<&str> {
+        self.vars.get(name).and_then(|var| var.value.as_deref())
+    }
+
+    pub fn set_var(&mut self, name: &str, value: String) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.value = Some(value),
+            None => {
+                let var = Var {
+                    value: Some(value),
+                    exported: false,
+                };
+                self.vars.insert(name.to_string(), var);
+            }
+        }
+    }
+
+    pub fn set_exported(&mut self, name: &str, exported: bool) {
+        match self.vars.get_mut(name) {
+            Some(var) => var.exported = exported,
+            None if exported => {
+                let var = Var {
+                    value: None,
+                    exported,
+                };
+                self.vars.insert(name.to_string(), var);
+            }
+            None => {}
+        }
+    }
+
+    // Makes a variable local to the innermost function call. Returns false
+    // outside of functions.
+    pub fn make_local(&mut self, name: &str) -> bool {
+        let outside = self.vars.get(name).cloned();
+        match self.frames.last_mut() {
+            Some(frame) => {
+                frame.entry(name.to_string()).or_insert(outside);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn unset_var(&mut self, name: &str) {
+        self.vars.remove(name);
+    }
+
+    // The environment passed to commands: every exported variable that has
+    // a value.
+    pub fn environment(&self) -> Vec<(String, String)> {
+        let mut env: Vec<(String, String)> = self
+            .vars
+            .iter()
+            .filter(|(_, var)| var.exported)
+            .filter_map(|(name, var)| Some((name.clone(), var.value.clone()?)))
+            .collect();
+        env.sort();
+        env
+    }
+}
@@ -0,0 +1,147 @@
+// Signal dispositions and traps. An interactive shell ignores the signals
+// the terminal sends to the job in the foreground, so that only the job is
+// interrupted; the commands it starts get them back.
+
+use std::os::raw::c_int;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use nix::sys::signal::{self, SaFlags, SigAction, SigHandler, SigSet, Signal};
+
+use crate::shell::Shell;
+
+// What Ctrl-C, Ctrl-\ and Ctrl-Z send.
+pub const INTERACTIVE: [Signal; 3] = [Signal::SIGINT, Signal::SIGQUIT, Signal::SIGTSTP];
+
+// Ignores signals in the shell itself. They are put back to the default in
+// its children by `restore`.
+pub fn ignore(shell: &mut Shell, signals: &[Signal]) {
+    set_ignored(signals);
+    for &ignored in signals {
+        if !shell.ignored.contains(&ignored) {
+            shell.ignored.push(ignored);
+        }
+    }
+}
+
+// Ignores signals for good: the commands started afterwards inherit that.
+pub fn set_ignored(signals: &[Signal]) {
+    for &ignored in signals {
+        unsafe { signal::signal(ignored, SigHandler::SigIgn) }.ok();
+    }
+}
+
+// Puts back the default action of signals. Runs in a child, where it must
+// not allocate.
+pub fn restore(signals: &[Signal]) {
+    for &ignored in signals {
+        unsafe { signal::signal(ignored, SigHandler::SigDfl) }.ok();
+    }
+}
+
+// What a trap can be set on: a signal, or one of the shell's own events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Trap {
+    Exit,
+    Signal(Signal),
+    Debug,
+    Err,
+    Return,
+}
+
+impl Trap {
+    // Takes a number, or a name with or without the `SIG` prefix and in any
+    // case.
+    pub fn parse(spec: &str) -> Option<Trap> {
+        if let Ok(number) = spec.parse::<i32>() {
+            return match number {
+                0 => Some(Trap::Exit),
+                _ => Signal::try_from(number).ok().map(Trap::Signal),
+            };
+        }
+
+        let name = spec.to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        match name {
+            "EXIT" => Some(Trap::Exit),
+            "DEBUG" => Some(Trap::Debug),
+            "ERR" => Some(Trap::Err),
+            "RETURN" => Some(Trap::Return),
+            _ => format!("SIG{}", name).parse().ok().map(Trap::Signal),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Trap::Exit => "EXIT",
+            Trap::Signal(signal) => signal.as_str(),
+            Trap::Debug => "DEBUG",
+            Trap::Err => "ERR",
+            Trap::Return => "RETURN",
+        }
+    }
+}
+
+// The signals caught since `take_pending` was last called, one bit each.
+static PENDING: AtomicU64 = AtomicU64::new(0);
+
+extern "C" fn catch(signal: c_int) {
+    PENDING.fetch_or(1 << signal, Ordering::SeqCst);
+}
+
+// The caught signals whose traps are still to be run.
+pub fn take_pending() -> Vec<Signal> {
+    let pending = PENDING.swap(0, Ordering::SeqCst);
+    Signal::iterator()
+        .filter(|&signal| pending & (1 << signal as i32) != 0)
+        .collect()
+}
+
+// Sets or, with no action, resets a trap. A signal with an empty action is
+// ignored; any other action is run between commands once the signal comes.
+pub fn set_trap(shell: &mut Shell, trap: Trap, action: Option<String>) {
+    if let Trap::Signal(signal) = trap {
+        let handler = match &action {
+            Some(action) if action.is_empty() => SigHandler::SigIgn,
+            Some(_) => SigHandler::Handler(catch),
+            None if shell.ignored.contains(&signal) => SigHandler::SigIgn,
+            None => SigHandler::SigDfl,
+        };
+        let action = SigAction::new(handler, SaFlags::SA_RESTART, SigSet::empty());
+        unsafe { signal::sigaction(signal, &action) }.ok();
+    }
+
+    shell.parent_traps = None;
+    match action {
+        Some(action) => shell.traps.insert(trap, action),
+        None => shell.traps.remove(&trap),
+    };
+}
+
+// The signals the commands the shell starts must get back, which excludes
+// those a trap ignores.
+pub fn for_children(shell: &Shell) -> Vec<Signal> {
+    shell
+        .ignored
+        .iter()
+        .copied()
+        .filter(|&signal| {
+            !matches!(shell.traps.get(&Trap::Signal(signal)), Some(action) if action.is_empty())
+        })
+        .collect()
+}
+
+// A forked copy of the shell starts without the traps of its parent, except
+// that signals a trap ignores stay ignored.
+pub fn enter_subshell(shell: &mut Shell) {
+    restore(&for_children(shell));
+    shell.ignored.clear();
+    shell.parent_traps = Some(shell.traps.clone());
+    shell.traps.retain(|trap, action| {
+        if let Trap::Signal(signal) = trap {
+            if !action.is_empty() {
+                unsafe { signal::signal(*signal, SigHandler::SigDfl) }.ok();
+            }
+        }
+        action.is_empty()
+    });
+}
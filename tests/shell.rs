@@ -0,0 +1,132 @@
+use std::process::Command;
+
+// Runs a `-c` command string and returns its standard output and status.
+fn run(script: &str) -> (String, i32) {
+    let output = Command::new(env!("CARGO_BIN_EXE_codecrafters-shell"))
+        .arg("-c")
+        .arg(script)
+        .output()
+        .expect("failed to run the shell");
+    (
+        String::from_utf8_lossy(&output.stdout).into_owned(),
+        output.status.code().unwrap_or(-1),
+    )
+}
+
+#[test]
+fn positional_parameters() {
+    assert_eq!(run("set -- a b c; echo $1 ${2} ${3}").0, "a b c\n");
+    assert_eq!(run("set -- a; echo \"[$2]\"").0, "[]\n");
+}
+
+#[test]
+fn all_zero_positional_parameter_is_unset() {
+    assert_eq!(
+        run("set -- a; echo \"[${00}]\"; echo done"),
+        ("[]\ndone\n".to_string(), 0)
+    );
+}
+
+#[test]
+fn brace_sequences() {
+    assert_eq!(
+        run("echo {1..3} {05..1..2} {a..c}").0,
+        "1 2 3 05 03 01 a b c\n"
+    );
+}
+
+#[test]
+fn brace_sequences_near_the_integer_limits() {
+    assert_eq!(
+        run("echo {9223372036854775806..9223372036854775807}").0,
+        "9223372036854775806 9223372036854775807\n"
+    );
+    assert_eq!(run("echo {1..5..-9223372036854775808}").0, "1\n");
+    assert_eq!(
+        run("echo {1..-9223372036854775808}").0,
+        "{1..-9223372036854775808}\n"
+    );
+}
+
+#[test]
+fn backslash_newline_joins_lines() {
+    assert_eq!(run("echo a \\\nb").0, "a b\n");
+}
+
+#[test]
+fn negated_pipelines() {
+    assert_eq!(
+        run("if ! false; then echo yes; fi; ! true; echo $?; ! false | true; echo $?").0,
+        "yes\n1\n1\n"
+    );
+    assert_eq!(
+        run("set -e; trap 'echo err' ERR; ! true; ! false; echo alive"),
+        ("alive\n".to_string(), 0)
+    );
+}
+
+#[test]
+fn err_trap_runs_for_a_failed_function_call() {
+    assert_eq!(run("trap 'echo err' ERR; f(){ false; }; f").0, "err\n");
+    assert_eq!(run("trap 'echo err' ERR; { false; }").0, "err\n");
+}
+
+#[test]
+fn unset_parameter_error_stops_a_script() {
+    assert_eq!(
+        run("echo before; echo ${x:?oops}; echo after"),
+        ("before\n".to_string(), 1)
+    );
+    assert_eq!(run("x=1; echo ${x:?oops}").0, "1\n");
+}
+
+#[test]
+fn case_inside_command_substitution() {
+    assert_eq!(run("echo $(case x in x) echo y;; esac) z").0, "y z\n");
+    assert_eq!(run("echo $( (echo sub); echo $(echo in) )").0, "sub in\n");
+}
+
+#[test]
+fn arithmetic_for_expands_its_clauses_each_time() {
+    assert_eq!(
+        run("for ((i=0; $i<3; i++)); do echo $i; done").0,
+        "0\n1\n2\n"
+    );
+    assert_eq!(
+        run("for ((i=0; i<6; i+=${step:-1})); do echo $i; step=2; done").0,
+        "0\n2\n4\n"
+    );
+}
+
+#[test]
+fn jobs_are_listed_in_a_pipeline() {
+    assert_eq!(
+        run("sleep 1 & jobs | cat; jobs -p %1 | wc -l; (sleep 0 & jobs)").0,
+        "[1]+  Running                 sleep 1 &\n1\n[1]+  Running                 sleep 0 &\n"
+    );
+}
+
+#[test]
+fn chained_aliases() {
+    let setup = "shopt -s expand_aliases; alias a1='echo ' a3=wow q=a1 s='a1 ' r='a1 x'\n";
+    assert_eq!(
+        run(&format!("{}q a3; s a3; r a3", setup)).0,
+        "a3\nwow\nx a3\n"
+    );
+}
+
+#[test]
+fn builtin_write_errors_are_reported() {
+    assert_eq!(
+        run("echo x 2>&1 > /dev/full; echo $?").0,
+        "echo: write error: No space left on device\n1\n"
+    );
+}
+
+#[test]
+fn env_lists_the_exported_variables() {
+    assert_eq!(
+        run("export A=1; B=2; C=3 env | grep '^[ABC]='; env D=4 /bin/sh -c 'echo $D'").0,
+        "A=1\nC=3\n4\n"
+    );
+}